@@ -73,10 +73,8 @@ fn impl_for_struct(derive_target: &Ident, data: &DataStruct, attrs: &[Attribute]
       let mut fields_name = Vec::<Ident>::with_capacity(fields.unnamed.len());
       let mut fields_read = Vec::<TokenStream>::with_capacity(fields.unnamed.len());
       let mut fields_write = Vec::<TokenStream>::with_capacity(fields.unnamed.len());
-      let mut i = 0usize;
-      for field in fields.unnamed.iter() {
+      for (i, field) in fields.unnamed.iter().enumerate() {
         let ident = i.to_string();
-        i += 1;
         let let_ident = Ident::new(&format!("field_{}", ident), Span::call_site());
         match &field.ty {
           Type::Path(path) => {
@@ -191,7 +189,7 @@ fn type_array(let_ident: &Ident, ident: &str, array: &TypeArray, attrs: &[Attrib
     Type::Path(path) => {
       let tyident = path.path.get_ident().unwrap();
       let len = &array.len;
-      let (read_fn, write_fn) = get_rw_fn(&"elem", false, tyident, attrs);
+      let (read_fn, write_fn) = get_rw_fn("elem", false, tyident, attrs);
       let primitive_magic = if tyident == "u8" || tyident == "u16" || tyident == "u32" || tyident == "u64" { quote!(let elem = *elem;) }
                             else { TokenStream::new() };
       let write = quote! {
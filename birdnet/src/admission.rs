@@ -0,0 +1,224 @@
+use crate::codable::{self, Codable};
+use crate::constants::OFFLINE_MAGIC;
+use crate::protocol::PacketIdentifiers;
+use crate::protocol::disconnect::{ConnectionBanned, NoFreeIncomingConnections, AlreadyConnected, IpRecentryConnected};
+use crate::socket::SocketConfiguration;
+
+use std::collections::HashMap;
+use std::fmt;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[derive(Debug)]
+pub struct InvalidNetwork;
+
+//An address range such as 10.0.0.0/8. IPv4-mapped IPv6 addresses are treated as IPv4.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct IpNetwork {
+  address: IpAddr,
+  prefix: u8,
+}
+
+impl IpNetwork {
+  //The host bits of `address` are cleared. None if the prefix is longer than the address.
+  pub fn new(address: IpAddr, prefix: u8) -> Option<IpNetwork> {
+    let address = address.to_canonical();
+    let bits = match address {
+      IpAddr::V4(_) => 32,
+      IpAddr::V6(_) => 128,
+    };
+    if prefix > bits {
+      return None;
+    }
+    Some(IpNetwork {
+      address: mask(address, prefix),
+      prefix,
+    })
+  }
+
+  pub fn address(&self) -> IpAddr {
+    self.address
+  }
+
+  pub fn prefix(&self) -> u8 {
+    self.prefix
+  }
+
+  pub fn contains(&self, address: IpAddr) -> bool {
+    let address = address.to_canonical();
+    address.is_ipv4() == self.address.is_ipv4() && mask(address, self.prefix) == self.address
+  }
+}
+
+impl From<IpAddr> for IpNetwork {
+  fn from(address: IpAddr) -> Self {
+    let address = address.to_canonical();
+    let prefix = if address.is_ipv4() { 32 } else { 128 };
+    IpNetwork { address, prefix }
+  }
+}
+
+//Accepts `address/prefix` or a single address.
+impl FromStr for IpNetwork {
+  type Err = InvalidNetwork;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.split_once('/') {
+      Some((address, prefix)) => {
+        let address = address.parse().map_err(|_| InvalidNetwork)?;
+        let prefix = prefix.parse().map_err(|_| InvalidNetwork)?;
+        IpNetwork::new(address, prefix).ok_or(InvalidNetwork)
+      },
+      None => s.parse::<IpAddr>().map(IpNetwork::from).map_err(|_| InvalidNetwork),
+    }
+  }
+}
+
+impl fmt::Display for IpNetwork {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}/{}", self.address, self.prefix)
+  }
+}
+
+fn mask(address: IpAddr, prefix: u8) -> IpAddr {
+  match address {
+    IpAddr::V4(address) => {
+      let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+      IpAddr::V4(Ipv4Addr::from(u32::from(address) & mask))
+    },
+    IpAddr::V6(address) => {
+      let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+      IpAddr::V6(Ipv6Addr::from(u128::from(address) & mask))
+    },
+  }
+}
+
+struct Ban {
+  network: IpNetwork,
+  expires: Option<Instant>,
+}
+
+#[derive(Default)]
+pub struct BanList {
+  bans: Vec<Ban>,
+}
+
+impl BanList {
+  pub fn new() -> BanList {
+    Default::default()
+  }
+
+  //Banning a network again replaces its expiry. None bans until `unban`.
+  pub fn ban(&mut self, network: IpNetwork, duration: Option<Duration>, now: Instant) {
+    self.unban(network);
+    self.bans.push(Ban {
+      network,
+      expires: duration.map(|duration| now + duration),
+    });
+  }
+
+  pub fn unban(&mut self, network: IpNetwork) -> bool {
+    let len = self.bans.len();
+    self.bans.retain(|ban| ban.network != network);
+    self.bans.len() != len
+  }
+
+  pub fn is_banned(&mut self, address: IpAddr, now: Instant) -> bool {
+    self.bans.retain(|ban| ban.expires.is_none_or(|expires| now < expires));
+    self.bans.iter().any(|ban| ban.network.contains(address))
+  }
+
+  pub fn networks(&self) -> impl Iterator<Item = IpNetwork> + '_ {
+    self.bans.iter().map(|ban| ban.network)
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Rejection {
+  Banned,
+  NoFreeIncomingConnections,
+  AlreadyConnected,
+  IpRecentlyConnected,
+}
+
+impl Rejection {
+  pub fn to_bytes(self, server_id: u64) -> codable::Result<Vec<u8>> {
+    match self {
+      Rejection::Banned => ConnectionBanned {
+        id: PacketIdentifiers::ConnectionBanned as u8,
+        offline_magic: OFFLINE_MAGIC,
+        server_id,
+      }.to_bytes(),
+      Rejection::NoFreeIncomingConnections => NoFreeIncomingConnections {
+        id: PacketIdentifiers::NoFreeIncomingConnections as u8,
+        offline_magic: OFFLINE_MAGIC,
+        server_id,
+      }.to_bytes(),
+      Rejection::AlreadyConnected => AlreadyConnected {
+        id: PacketIdentifiers::AlreadyConnected as u8,
+        offline_magic: OFFLINE_MAGIC,
+        server_id,
+      }.to_bytes(),
+      Rejection::IpRecentlyConnected => IpRecentryConnected {
+        id: PacketIdentifiers::IpRecentryConnected as u8,
+        offline_magic: OFFLINE_MAGIC,
+        server_id,
+      }.to_bytes(),
+    }
+  }
+}
+
+//Decides whether the listener opens a new session for a handshake.
+//Duplicate handshakes from a connected address are detected by the session itself.
+pub(crate) struct Admission {
+  max_connections: usize,
+  reconnect_interval: Duration,
+  bans: Arc<Mutex<BanList>>,
+  recent: HashMap<IpAddr, Instant>,
+}
+
+impl Admission {
+  pub fn new(config: &SocketConfiguration, bans: Arc<Mutex<BanList>>) -> Admission {
+    Admission {
+      max_connections: config.max_connections,
+      reconnect_interval: config.reconnect_interval,
+      bans,
+      recent: HashMap::new(),
+    }
+  }
+
+  pub fn bans(&self) -> &Arc<Mutex<BanList>> {
+    &self.bans
+  }
+
+  pub fn is_banned(&self, address: IpAddr, now: Instant) -> bool {
+    self.bans.lock().unwrap().is_banned(address, now)
+  }
+
+  pub fn has_free_slots(&self, sessions: usize) -> bool {
+    sessions < self.max_connections
+  }
+
+  //Checked before a session is created for `address`; an accepted attempt starts its reconnect interval.
+  pub fn admit(&mut self, address: IpAddr, sessions: usize, now: Instant) -> Result<(), Rejection> {
+    if self.is_banned(address, now) {
+      return Err(Rejection::Banned);
+    }
+    if !self.has_free_slots(sessions) {
+      return Err(Rejection::NoFreeIncomingConnections);
+    }
+    let address = address.to_canonical();
+    if self.recent.get(&address).is_some_and(|last| now.saturating_duration_since(*last) < self.reconnect_interval) {
+      return Err(Rejection::IpRecentlyConnected);
+    }
+    self.recent.insert(address, now);
+    Ok(())
+  }
+
+  pub fn update(&mut self, now: Instant) {
+    let interval = self.reconnect_interval;
+    self.recent.retain(|_, last| now.saturating_duration_since(*last) < interval);
+  }
+}
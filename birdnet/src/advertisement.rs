@@ -0,0 +1,156 @@
+use std::fmt;
+use std::net::SocketAddr;
+use std::str::FromStr;
+
+//What an advertiser callback knows about the UnconnectedPing it answers.
+pub struct PingRequest {
+  pub address: SocketAddr,
+  //Established connections, handshakes excluded.
+  pub connections: usize,
+}
+
+//The information string of the listener's UnconnectedPong.
+pub(crate) enum Advertisement {
+  Static(String),
+  Dynamic(Box<dyn Fn(&PingRequest) -> String + Send>),
+}
+
+impl Advertisement {
+  pub fn build(&self, request: &PingRequest) -> String {
+    match self {
+      Advertisement::Static(information) => information.clone(),
+      Advertisement::Dynamic(advertiser) => advertiser(request),
+    }
+  }
+}
+
+impl Default for Advertisement {
+  fn default() -> Self {
+    Advertisement::Static(String::new())
+  }
+}
+
+#[derive(Debug)]
+pub struct InvalidAdvertisement;
+
+//The `MCPE;motd;protocol;version;players;max;server id;sub motd;game mode;game mode id;port;port v6;`
+//string Minecraft Bedrock servers advertise. Only the first six fields are required when parsing.
+//Text fields cannot contain ';', it is replaced when formatting.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub struct BedrockAdvertisement {
+  //MCPE, or MCEE for Education Edition.
+  pub edition: String,
+  pub motd: String,
+  pub protocol: u32,
+  pub version: String,
+  pub players: u32,
+  pub max_players: u32,
+  pub server_id: u64,
+  pub sub_motd: String,
+  pub game_mode: String,
+  pub game_mode_id: u8,
+  pub port: Option<u16>,
+  pub port_v6: Option<u16>,
+}
+
+impl Default for BedrockAdvertisement {
+  fn default() -> Self {
+    BedrockAdvertisement {
+      edition: "MCPE".to_string(),
+      motd: String::new(),
+      protocol: 0,
+      version: String::new(),
+      players: 0,
+      max_players: 0,
+      server_id: 0,
+      sub_motd: String::new(),
+      game_mode: "Survival".to_string(),
+      game_mode_id: 1,
+      port: None,
+      port_v6: None,
+    }
+  }
+}
+
+impl fmt::Display for BedrockAdvertisement {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let text = |s: &str| s.replace(';', ",");
+    let port = |port: Option<u16>| port.map(|port| port.to_string()).unwrap_or_default();
+    write!(f, "{};{};{};{};{};{};{};{};{};{};{};{};",
+      text(&self.edition), text(&self.motd), self.protocol, text(&self.version),
+      self.players, self.max_players, self.server_id, text(&self.sub_motd),
+      text(&self.game_mode), self.game_mode_id, port(self.port), port(self.port_v6))
+  }
+}
+
+impl FromStr for BedrockAdvertisement {
+  type Err = InvalidAdvertisement;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    let mut fields = s.split(';');
+    let mut next = || fields.next().filter(|field| !field.is_empty());
+    let defaults = BedrockAdvertisement::default();
+    Ok(BedrockAdvertisement {
+      edition: next().ok_or(InvalidAdvertisement)?.to_string(),
+      motd: next().unwrap_or_default().to_string(),
+      protocol: number(next())?,
+      version: next().unwrap_or_default().to_string(),
+      players: number(next())?,
+      max_players: number(next())?,
+      server_id: optional_number(next())?.unwrap_or(defaults.server_id),
+      sub_motd: next().unwrap_or_default().to_string(),
+      game_mode: next().map_or(defaults.game_mode, str::to_string),
+      game_mode_id: optional_number(next())?.unwrap_or(defaults.game_mode_id),
+      port: optional_number(next())?,
+      port_v6: optional_number(next())?,
+    })
+  }
+}
+
+fn number<T: FromStr>(field: Option<&str>) -> Result<T, InvalidAdvertisement> {
+  field.ok_or(InvalidAdvertisement)?.parse().map_err(|_| InvalidAdvertisement)
+}
+
+fn optional_number<T: FromStr>(field: Option<&str>) -> Result<Option<T>, InvalidAdvertisement> {
+  field.map(|field| number(Some(field))).transpose()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn bedrock_round_trip() {
+    let advertisement = BedrockAdvertisement {
+      motd: "A;B".to_string(),
+      protocol: 766,
+      version: "1.21.50".to_string(),
+      players: 3,
+      max_players: 20,
+      server_id: 12345,
+      sub_motd: "World".to_string(),
+      port: Some(19132),
+      port_v6: Some(19133),
+      ..Default::default()
+    };
+    let text = advertisement.to_string();
+    assert_eq!(text, "MCPE;A,B;766;1.21.50;3;20;12345;World;Survival;1;19132;19133;");
+    let parsed: BedrockAdvertisement = text.parse().unwrap();
+    assert_eq!(parsed, BedrockAdvertisement { motd: "A,B".to_string(), ..advertisement });
+  }
+
+  #[test]
+  fn bedrock_parse_minimal() {
+    let parsed: BedrockAdvertisement = "MCEE;Class;100;1.0;0;30".parse().unwrap();
+    assert_eq!(parsed, BedrockAdvertisement {
+      edition: "MCEE".to_string(),
+      motd: "Class".to_string(),
+      protocol: 100,
+      version: "1.0".to_string(),
+      max_players: 30,
+      ..Default::default()
+    });
+    assert!("MCPE;motd;766;1.0;3".parse::<BedrockAdvertisement>().is_err());
+    assert!("MCPE;motd;new;1.0;3;20".parse::<BedrockAdvertisement>().is_err());
+  }
+}
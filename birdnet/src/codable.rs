@@ -3,8 +3,19 @@ use bytes::{Buf, BufMut};
 pub trait Codable: Sized {
   fn encode(&self, buffer: &mut dyn BufMut) -> Result<()>;
   fn decode(buffer: &mut dyn Buf) -> Result<Self>;
+
+  fn to_bytes(&self) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    self.encode(&mut buffer)?;
+    Ok(buffer)
+  }
+
+  fn from_bytes(mut bytes: &[u8]) -> Result<Self> {
+    Self::decode(&mut bytes)
+  }
 }
 
+#[derive(Debug)]
 pub enum BytesCodingError {
   NotEnoughRemaining,
   InvalidInput(String),
@@ -92,7 +103,7 @@ impl<T: Buf> ReadBytesExt for T {
   }
 
   fn read_exact(&mut self, buffer: &mut [u8]) -> Result<()> {
-    if self.remaining() >= buffer.len() { Ok(self.copy_to_slice(buffer)) }
+    if self.remaining() >= buffer.len() { self.copy_to_slice(buffer); Ok(()) }
     else { Err(BytesCodingError::NotEnoughRemaining) }
   }
 }
@@ -101,7 +112,7 @@ macro_rules! impl_write_wrap {
   ($t:ty, $bytes:expr, be) => {
     paste::item! {
       fn [<write_ $t _be>](&mut self, v: $t) -> Result<()> {
-        if self.remaining_mut() >= $bytes { Ok(self.[<put_ $t>](v)) }
+        if self.remaining_mut() >= $bytes { self.[<put_ $t>](v); Ok(()) }
         else { Err(BytesCodingError::NotEnoughRemaining) }
       }
     }
@@ -109,7 +120,7 @@ macro_rules! impl_write_wrap {
   ($t:ty, $bytes:expr, le) => {
     paste::item! {
       fn [<write_ $t _le>](&mut self, v: $t) -> Result<()> {
-        if self.remaining_mut() >= $bytes { Ok(self.[<put_ $t _le>](v)) }
+        if self.remaining_mut() >= $bytes { self.[<put_ $t _le>](v); Ok(()) }
         else { Err(BytesCodingError::NotEnoughRemaining) }
       }
     }
@@ -118,7 +129,7 @@ macro_rules! impl_write_wrap {
 
 impl<T: BufMut> WriteBytesExt for T {
   fn write_u8(&mut self, v: u8) -> Result<()> {
-    if self.remaining_mut() >= 1 { Ok(self.put_u8(v)) }
+    if self.remaining_mut() >= 1 { self.put_u8(v); Ok(()) }
     else { Err(BytesCodingError::NotEnoughRemaining) }
   }
 
@@ -127,7 +138,7 @@ impl<T: BufMut> WriteBytesExt for T {
   impl_write_wrap!(u64, 8, be);
   impl_write_wrap!(u128, 16, be);
   fn write_u24_be(&mut self, v: u32) -> Result<()> {
-    if self.remaining_mut() >= 3 { Ok(self.put_uint(v as u64, 3)) }
+    if self.remaining_mut() >= 3 { self.put_uint(v as u64, 3); Ok(()) }
     else { Err(BytesCodingError::NotEnoughRemaining) }
   }
 
@@ -136,12 +147,12 @@ impl<T: BufMut> WriteBytesExt for T {
   impl_write_wrap!(u64, 8, le);
   impl_write_wrap!(u128, 16, le);
   fn write_u24_le(&mut self, v: u32) -> Result<()> {
-    if self.remaining_mut() >= 3 { Ok(self.put_uint_le(v as u64, 3)) }
+    if self.remaining_mut() >= 3 { self.put_uint_le(v as u64, 3); Ok(()) }
     else { Err(BytesCodingError::NotEnoughRemaining) }
   }
 
   fn write_all(&mut self, data: &[u8]) -> Result<()> {
-    if self.remaining_mut() >= data.len() { Ok(self.put_slice(data)) }
+    if self.remaining_mut() >= data.len() { self.put_slice(data); Ok(()) }
     else { Err(BytesCodingError::NotEnoughRemaining) }
   }
 }
@@ -0,0 +1,187 @@
+use crate::connector::{Connector, ConnectError};
+use crate::constants::{PacketReliability, PacketPriority};
+use crate::session;
+use crate::socket::SocketConfiguration;
+
+use std::net::SocketAddr;
+use std::time::Duration;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use async_channel::{unbounded, Sender, Receiver};
+
+pub struct Message {
+  pub payload: Vec<u8>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DisconnectReason {
+  //The peer sent DisconnectionNotification.
+  Remote,
+  //The connection was closed locally, or the task driving it has stopped.
+  Closed,
+  //Nothing was received from the peer for longer than the configured timeout.
+  Timeout,
+  //The peer sent something the session cannot recover from, e.g. more split messages than allowed.
+  ProtocolViolation,
+}
+
+#[derive(Debug)]
+pub struct Disconnected {
+  pub reason: DisconnectReason,
+}
+
+#[derive(Debug)]
+pub enum SendError {
+  InvalidChannel(u8),
+  //Needs more fragments than `max_split_count` allows.
+  MessageTooLarge(usize),
+  Closed,
+}
+
+//Commands carry the session id besides the address, so that a handle outliving its session
+//does not reach a new one with the same address.
+pub(crate) enum Command {
+  Send {
+    address: SocketAddr,
+    session: u64,
+    payload: Vec<u8>,
+    reliability: PacketReliability,
+    priority: PacketPriority,
+    channel: u8,
+  },
+  Close {
+    address: SocketAddr,
+    session: u64,
+  },
+}
+
+pub(crate) enum Event {
+  Message(Message),
+  Disconnected(DisconnectReason),
+}
+
+//State the driving task publishes for the handle.
+struct Shared {
+  rtt: AtomicU64,//microseconds, u64::MAX until measured
+  closed: AtomicBool,
+}
+
+//The driving task's side of a connection.
+pub(crate) struct Remote {
+  events: Sender<Event>,
+  shared: Arc<Shared>,
+}
+
+impl Remote {
+  pub fn deliver(&self, event: Event) {
+    if let Event::Disconnected(_) = event {
+      self.shared.closed.store(true, Ordering::Relaxed);
+    }
+    let _ = self.events.try_send(event);
+  }
+
+  pub fn set_rtt(&self, rtt: Option<Duration>) {
+    let rtt = rtt.map_or(u64::MAX, |rtt| rtt.as_micros().min(u64::MAX as u128 - 1) as u64);
+    self.shared.rtt.store(rtt, Ordering::Relaxed);
+  }
+}
+
+pub struct Connection {
+  address: SocketAddr,
+  session: u64,
+  mtu_size: u16,
+  ordering_channels: usize,
+  max_payload_size: usize,
+  commands: Sender<Command>,
+  events: Receiver<Event>,
+  shared: Arc<Shared>,
+}
+
+impl Connection {
+  pub async fn connect(address: SocketAddr) -> Result<Connection, ConnectError> {
+    Connector::new().connect(address).await
+  }
+
+  pub(crate) fn pair(address: SocketAddr, session: u64, mtu_size: u16, config: &SocketConfiguration, commands: Sender<Command>) -> (Connection, Remote) {
+    let (sender, events) = unbounded();
+    let shared = Arc::new(Shared {
+      rtt: AtomicU64::new(u64::MAX),
+      closed: AtomicBool::new(false),
+    });
+    let connection = Connection {
+      address,
+      session,
+      mtu_size,
+      ordering_channels: config.ordering_channels,
+      max_payload_size: session::max_payload_size(mtu_size, config.max_split_count),
+      commands,
+      events,
+      shared: shared.clone(),
+    };
+    (connection, Remote { events: sender, shared })
+  }
+
+  pub fn address(&self) -> SocketAddr {
+    self.address
+  }
+
+  pub fn mtu_size(&self) -> u16 {
+    self.mtu_size
+  }
+
+  //Smoothed round-trip time measured from ACKs, None until the first one arrives.
+  pub fn rtt(&self) -> Option<Duration> {
+    match self.shared.rtt.load(Ordering::Relaxed) {
+      u64::MAX => None,
+      rtt => Some(Duration::from_micros(rtt)),
+    }
+  }
+
+  pub async fn send(&self, payload: Vec<u8>, reliability: PacketReliability, priority: PacketPriority, channel: u8) -> Result<(), SendError> {
+    if channel as usize >= self.ordering_channels {
+      return Err(SendError::InvalidChannel(channel));
+    }
+    if payload.len() > self.max_payload_size {
+      return Err(SendError::MessageTooLarge(payload.len()));
+    }
+    //Sends racing the disconnect are dropped by the driving task.
+    if self.shared.closed.load(Ordering::Relaxed) {
+      return Err(SendError::Closed);
+    }
+    let command = Command::Send {
+      address: self.address,
+      session: self.session,
+      payload,
+      reliability,
+      priority,
+      channel,
+    };
+    self.commands.send(command).await.map_err(|_| SendError::Closed)
+  }
+
+  //Delivers the messages already sent and notifies the peer, waiting at most the configured close timeout.
+  //Messages received in the meantime are discarded.
+  pub async fn close(self) {
+    if self.commands.send(Command::Close { address: self.address, session: self.session }).await.is_err() {
+      return;
+    }
+    while let Ok(Event::Message(_)) = self.events.recv().await {}
+  }
+
+  pub async fn recv(&self) -> Result<Message, Disconnected> {
+    match self.events.recv().await {
+      Ok(Event::Message(message)) => Ok(message),
+      Ok(Event::Disconnected(reason)) => Err(Disconnected { reason }),
+      Err(_) => Err(Disconnected { reason: DisconnectReason::Closed }),
+    }
+  }
+}
+
+impl Drop for Connection {
+  fn drop(&mut self) {
+    if self.shared.closed.load(Ordering::Relaxed) {
+      return;
+    }
+    let _ = self.commands.try_send(Command::Close { address: self.address, session: self.session });
+  }
+}
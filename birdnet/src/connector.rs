@@ -0,0 +1,123 @@
+use crate::codable::Codable;
+use crate::connection::{Connection, DisconnectReason};
+use crate::endpoint::Endpoint;
+use crate::driver::Driver;
+use crate::protocol::PacketIdentifiers;
+use crate::protocol::disconnect::IncompatibleProtocolVersion;
+use crate::session::SessionError;
+use crate::socket::SocketConfiguration;
+
+use std::time::Instant;
+use crate::runtime;
+use crate::udp;
+
+use std::io;
+use std::net::{SocketAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::Arc;
+use async_channel::bounded;
+use num_traits::FromPrimitive;
+use log::debug;
+
+#[derive(Debug)]
+pub enum ConnectError {
+  IncompatibleProtocolVersion { protocol: u8 },
+  ConnectionBanned,
+  AlreadyConnected,
+  NoFreeIncomingConnections,
+  IpRecentlyConnected,
+  Timeout,
+  //The session ended before the handshake completed.
+  Disconnected(DisconnectReason),
+  Protocol(SessionError),
+  Io(io::Error),
+}
+
+impl From<io::Error> for ConnectError {
+  fn from(e: io::Error) -> Self {
+    ConnectError::Io(e)
+  }
+}
+
+impl From<SessionError> for ConnectError {
+  fn from(e: SessionError) -> Self {
+    ConnectError::Protocol(e)
+  }
+}
+
+impl ConnectError {
+  //Maps a rejection packet sent by the server, offline or inside the session.
+  pub(crate) fn from_packet(buffer: &[u8]) -> Option<ConnectError> {
+    let id = *buffer.first()?;
+    if id == PacketIdentifiers::IncompatibleProtocolVersion as u8 {
+      let packet = IncompatibleProtocolVersion::from_bytes(buffer).ok()?;
+      return Some(ConnectError::IncompatibleProtocolVersion { protocol: packet.protocol });
+    }
+    Self::from_id(id)
+  }
+
+  pub(crate) fn from_id(id: u8) -> Option<ConnectError> {
+    match PacketIdentifiers::from_u8(id)? {
+      PacketIdentifiers::ConnectionBanned => Some(ConnectError::ConnectionBanned),
+      PacketIdentifiers::AlreadyConnected => Some(ConnectError::AlreadyConnected),
+      PacketIdentifiers::NoFreeIncomingConnections => Some(ConnectError::NoFreeIncomingConnections),
+      PacketIdentifiers::IpRecentryConnected => Some(ConnectError::IpRecentlyConnected),
+      _ => None,
+    }
+  }
+}
+
+pub struct Connector {
+  config: SocketConfiguration,
+  client_id: u64,
+}
+
+impl Default for Connector {
+  fn default() -> Self {
+    Self::new()
+  }
+}
+
+impl Connector {
+  pub fn new() -> Connector {
+    Self::with_configuration(Default::default())
+  }
+
+  pub fn with_configuration(config: SocketConfiguration) -> Connector {
+    Connector {
+      config,
+      client_id: config.guid.unwrap_or_else(rand::random),
+    }
+  }
+
+  pub fn client_id(&self) -> u64 {
+    self.client_id
+  }
+
+  //Binds a new socket and runs the handshake on it. The socket is connected,
+  //so an unreachable server fails with an IO error instead of timing out, and DF is set
+  //while the MTU is probed.
+  pub async fn connect(&self, address: SocketAddr) -> Result<Connection, ConnectError> {
+    let local = if address.is_ipv4() { Ipv4Addr::UNSPECIFIED.into() } else { Ipv6Addr::UNSPECIFIED.into() };
+    let socket = self.config.bind(SocketAddr::new(local, 0))?;
+    socket.connect(address).await?;
+    let socket = Arc::new(socket);
+    if let Err(e) = udp::set_dont_fragment(&socket, true) {
+      debug!("probing the MTU without DF: {}", e);
+    }
+
+    let now = Instant::now();
+    let mut endpoint = Endpoint::client(self.config, now);
+    endpoint.set_guid(self.client_id);
+    endpoint.connect(address, now)?;
+    let (reply, result) = bounded(1);
+    let mut driver = Driver::new(socket.clone(), endpoint);
+    driver.connect(address, reply);
+    runtime::spawn(async move {
+      let _ = driver.run().await;
+    });
+    let connection = result.recv().await.unwrap_or(Err(ConnectError::Disconnected(DisconnectReason::Closed)))?;
+    //Datagrams above a path MTU that shrinks later are fragmented rather than refused.
+    let _ = udp::set_dont_fragment(&socket, false);
+    Ok(connection)
+  }
+}
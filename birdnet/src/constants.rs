@@ -4,8 +4,18 @@ pub const OFFLINE_MAGIC: [u64; 2] = [ 0x00ffff00fefefefe, 0xfdfdfdfd12345678 ];
 
 pub const NUMBER_OF_INTERNAL_IDS: usize = 20;
 
-#[derive(FromPrimitive, ToPrimitive, Clone, Copy, PartialEq)]
+pub const NUMBER_OF_ORDERED_STREAMS: usize = 32;
+
+pub const MAXIMUM_MTU_SIZE: u16 = 1492;
+
+pub const MINIMUM_MTU_SIZE: u16 = 400;
+
+//Probed by clients in this order until the server answers.
+pub const MTU_PROBE_SIZES: [u16; 3] = [1492, 1200, 576];
+
+#[derive(FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, Default)]
 pub enum PacketReliability {
+  #[default]
   Unreliable,
   UnreliableSequenced,
   Reliable,
@@ -16,10 +26,6 @@ pub enum PacketReliability {
   ReliableOrderedWithAckReceipt,
 }
 
-impl Default for PacketReliability {
-  fn default() -> Self { Self::Unreliable }
-}
-
 impl PacketReliability {
   pub fn is_unreliable(self) -> bool {
     self == Self::Unreliable ||
@@ -46,7 +52,7 @@ impl PacketReliability {
   }
 }
 
-#[derive(FromPrimitive, ToPrimitive)]
+#[derive(FromPrimitive, ToPrimitive, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PacketPriority {
   Immediate,
   High,
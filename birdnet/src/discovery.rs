@@ -0,0 +1,175 @@
+use crate::codable::Codable;
+use crate::constants::OFFLINE_MAGIC;
+use crate::protocol::PacketIdentifiers;
+use crate::protocol::ping::{UnconnectedPing, UnconnectedPong};
+use crate::runtime::{self, UdpSocket};
+
+use std::collections::HashSet;
+use std::net::{Ipv4Addr, Ipv6Addr};
+use std::time::{Duration, Instant};
+use std::io::{self, ErrorKind};
+use std::net::SocketAddr;
+use async_channel::{unbounded, Sender};
+use futures_lite::Stream;
+
+//All nodes on the link.
+const IPV6_ALL_NODES: Ipv6Addr = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 1);
+
+const DEFAULT_WINDOW: Duration = Duration::from_secs(1);
+
+pub struct DiscoveredServer {
+  pub address: SocketAddr,
+  pub server_id: u64,
+  pub latency: Duration,
+  pub information: String,
+}
+
+//Finds servers on the local network by pinging a port on every host.
+pub struct Discovery {
+  targets: Vec<SocketAddr>,
+  window: Duration,
+  open_connections_only: bool,
+  client_id: u64,
+}
+
+impl Discovery {
+  //Pings `port` on the IPv4 broadcast address and the IPv6 all-nodes multicast group.
+  pub fn new(port: u16) -> Discovery {
+    Self::with_targets(vec![
+      SocketAddr::new(Ipv4Addr::BROADCAST.into(), port),
+      SocketAddr::new(IPV6_ALL_NODES.into(), port),
+    ])
+  }
+
+  //Pings the given addresses instead, e.g. a subnet's broadcast address or a loopback listener.
+  pub fn with_targets(targets: Vec<SocketAddr>) -> Discovery {
+    Discovery {
+      targets,
+      window: DEFAULT_WINDOW,
+      open_connections_only: false,
+      client_id: rand::random(),
+    }
+  }
+
+  //How long pongs are collected after the pings are sent.
+  pub fn set_window(&mut self, window: Duration) {
+    self.window = window;
+  }
+
+  //Sends UnconnectedPingOpenConnection, which only servers with free slots answer.
+  pub fn set_open_connections_only(&mut self, open_connections_only: bool) {
+    self.open_connections_only = open_connections_only;
+  }
+
+  //Every server is yielded once, the stream ends when the window has elapsed.
+  //Fails if no target could be pinged.
+  pub async fn discover(&self) -> io::Result<impl Stream<Item = DiscoveredServer>> {
+    let (sender, servers) = unbounded();
+    let id = if self.open_connections_only { PacketIdentifiers::UnconnectedPingOpenConnection } else { PacketIdentifiers::UnconnectedPing };
+    let mut pinged = false;
+    let mut error = None;
+    for ipv4 in [true, false] {
+      let targets: Vec<SocketAddr> = self.targets.iter().copied().filter(|target| target.is_ipv4() == ipv4).collect();
+      if targets.is_empty() {
+        continue;
+      }
+      match self.ping(ipv4, &targets, id).await {
+        Ok((socket, epoch)) => {
+          runtime::spawn(collect(socket, epoch, epoch + self.window, sender.clone()));
+          pinged = true;
+        },
+        Err(e) => error = Some(e),
+      }
+    }
+    match error {
+      Some(e) if !pinged => Err(e),
+      _ => Ok(servers),
+    }
+  }
+
+  async fn ping(&self, ipv4: bool, targets: &[SocketAddr], id: PacketIdentifiers) -> io::Result<(UdpSocket, Instant)> {
+    let local = if ipv4 { "0.0.0.0:0" } else { "[::]:0" };
+    let socket = UdpSocket::bind(local).await?;
+    if ipv4 {
+      socket.set_broadcast(true)?;
+    }
+    let epoch = Instant::now();
+    let ping = UnconnectedPing {
+      id: id as u8,
+      ping_time: 0,
+      offline_magic: OFFLINE_MAGIC,
+      client_id: self.client_id,
+    };
+    let ping = ping.to_bytes().map_err(|e| io::Error::new(ErrorKind::InvalidData, format!("{:?}", e)))?;
+    let mut sent = false;
+    let mut error = None;
+    for target in targets {
+      match socket.send_to(&ping, target).await {
+        Ok(_) => sent = true,
+        Err(e) => error = Some(e),
+      }
+    }
+    match error {
+      Some(e) if !sent => Err(e),
+      _ => Ok((socket, epoch)),
+    }
+  }
+}
+
+async fn collect(socket: UdpSocket, epoch: Instant, deadline: Instant, servers: Sender<DiscoveredServer>) {
+  let mut buffer = vec![0u8; 2048];
+  let mut seen = HashSet::new();
+  loop {
+    let remaining = deadline.saturating_duration_since(Instant::now());
+    let (size, address) = match runtime::timeout(remaining, socket.recv_from(&mut buffer)).await {
+      Some(Ok(received)) => received,
+      Some(Err(_)) => continue,
+      None => return,
+    };
+    let pong = match UnconnectedPong::from_bytes(&buffer[..size]) {
+      Ok(pong) if pong.id == PacketIdentifiers::UnconnectedPong as u8 && pong.offline_magic == OFFLINE_MAGIC => pong,
+      _ => continue,
+    };
+    if !seen.insert(address) {
+      continue;
+    }
+    //The ping was sent at the epoch, ping_time is only echoed.
+    let server = DiscoveredServer {
+      address,
+      server_id: pong.server_id,
+      latency: epoch.elapsed(),
+      information: pong.information.into_inner(),
+    };
+    if servers.send(server).await.is_err() {
+      return;
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::listener::Listener;
+  use crate::socket::SocketConfiguration;
+  use futures_lite::StreamExt;
+  use std::sync::Arc;
+
+  #[test]
+  fn discover_loopback_listener() {
+    runtime::block_on(async {
+      let config = SocketConfiguration::builder().guid(42).build().unwrap();
+      let socket = config.bind("127.0.0.1:0".parse().unwrap()).unwrap();
+      let address = socket.local_addr().unwrap();
+      let listener = Listener::with_configuration(Arc::new(socket), config);
+      listener.set_advertisement("MCPE;test;766;1.21.50;0;10;");
+
+      let mut discovery = Discovery::with_targets(vec![address]);
+      discovery.set_window(Duration::from_millis(500));
+      let servers: Vec<DiscoveredServer> = discovery.discover().await.unwrap().collect().await;
+      assert_eq!(servers.len(), 1);
+      assert_eq!((servers[0].address, servers[0].server_id), (address, 42));
+      assert_eq!(servers[0].information, "MCPE;test;766;1.21.50;0;10;");
+      listener.shutdown().await;
+    });
+  }
+}
@@ -0,0 +1,229 @@
+use crate::connection::{Connection, Command, DisconnectReason, Event, Message, Remote};
+use crate::connector::ConnectError;
+use crate::endpoint::{Endpoint, EndpointEvent};
+use crate::session::Session;
+
+use std::time::Instant;
+use crate::runtime::{self, UdpSocket};
+use crate::udp::{self, UdpIo};
+
+use std::collections::HashMap;
+use std::io::{self, ErrorKind};
+use std::iter;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use async_channel::{unbounded, Sender, Receiver};
+use futures_lite::future::{self, FutureExt};
+use log::{debug, error};
+
+//Runs an endpoint on a socket and connects its events to the connection handles.
+//Used by both the listener and the connector.
+pub(crate) struct Driver {
+  io: UdpIo,
+  endpoint: Endpoint,
+  commands_sender: Sender<Command>,
+  commands: Receiver<Command>,
+  connections: HashMap<SocketAddr, Remote>,
+  //Where accepted connections go, for a listening endpoint.
+  accepted: Option<Sender<Connection>>,
+  //Pending `Endpoint::connect` calls.
+  connects: HashMap<SocketAddr, Sender<Result<Connection, ConnectError>>>,
+  //Closed by the listener to shut down.
+  shutdown: Option<Receiver<()>>,
+}
+
+enum Input {
+  Packets,
+  Command(Command),
+  Shutdown,
+}
+
+impl Driver {
+  pub fn new(socket: Arc<UdpSocket>, endpoint: Endpoint) -> Driver {
+    let (commands_sender, commands) = unbounded();
+    Driver {
+      io: UdpIo::new(socket, endpoint.config()),
+      endpoint,
+      commands_sender,
+      commands,
+      connections: HashMap::new(),
+      accepted: None,
+      connects: HashMap::new(),
+      shutdown: None,
+    }
+  }
+
+  //Accepted connections are sent to `accepted`, and the task ends after `shutdown` is closed
+  //and every session has closed.
+  pub fn listen(&mut self, accepted: Sender<Connection>, shutdown: Receiver<()>) {
+    self.accepted = Some(accepted);
+    self.shutdown = Some(shutdown);
+  }
+
+  //Reports the outcome of a handshake started on the endpoint to `reply`.
+  pub fn connect(&mut self, address: SocketAddr, reply: Sender<Result<Connection, ConnectError>>) {
+    self.connects.insert(address, reply);
+  }
+
+  //Ends once the endpoint is idle and nothing can be accepted anymore, or with the error
+  //that made the socket unusable.
+  pub async fn run(mut self) -> io::Result<()> {
+    let mut closing = false;
+    loop {
+      let timeout = self.endpoint.poll_timeout();
+      let (io, commands, shutdown) = (&mut self.io, &self.commands, &self.shutdown);
+      let packet = async {
+        io.recv().await.map(|_| Input::Packets)
+      };
+      //The driver keeps a sender, so the channel never closes.
+      let command = async {
+        match commands.recv().await {
+          Ok(command) => Ok(Input::Command(command)),
+          Err(_) => future::pending().await,
+        }
+      };
+      let shutdown = async {
+        match shutdown {
+          Some(shutdown) if !closing => {
+            let _ = shutdown.recv().await;
+            Ok(Input::Shutdown)
+          },
+          _ => future::pending().await,
+        }
+      };
+      //Without a pending timer, only input wakes the driver.
+      let input = packet.race(command).race(shutdown);
+      let input = match timeout {
+        Some(timeout) => runtime::timeout(timeout.saturating_duration_since(Instant::now()), input).await,
+        None => Some(input.await),
+      };
+      match input {
+        Some(Ok(Input::Packets)) => {
+          let now = Instant::now();
+          for (remote, payload) in self.io.received() {
+            self.endpoint.handle(remote, payload, now);
+          }
+        },
+        Some(Ok(Input::Command(command))) => self.handle_command(command),
+        Some(Ok(Input::Shutdown)) => {
+          //Peers are notified, and nothing is accepted anymore.
+          self.endpoint.close_all(Instant::now());
+          self.accepted = None;
+          closing = true;
+        },
+        None => {},
+        Some(Err(e)) if udp::is_fatal(&e) => return Err(self.fail(e)),
+        Some(Err(e)) => self.handle_recv_error(e),
+      }
+      let now = Instant::now();
+      if self.endpoint.poll_timeout().is_some_and(|timeout| now >= timeout) {
+        self.endpoint.handle_timeout(now);
+      }
+      if let Err(e) = self.flush().await {
+        return Err(self.fail(e));
+      }
+      if self.endpoint.is_idle() && self.accepted.is_none() {
+        return Ok(());
+      }
+    }
+  }
+
+  fn handle_command(&mut self, command: Command) {
+    let (Command::Send { address, session, .. } | Command::Close { address, session }) = command;
+    if self.endpoint.session(address).map(Session::id) != Some(session) {
+      return;
+    }
+    match command {
+      Command::Send { address, payload, reliability, priority, channel, .. } => {
+        //`Connection::send` rejects what it can, this catches sends racing a close.
+        if let Err(e) = self.endpoint.send(address, payload, reliability, priority, channel, Instant::now()) {
+          debug!("Dropped message to {}: {:?}", address, e);
+        }
+      },
+      Command::Close { address, .. } => self.endpoint.close(address, Instant::now()),
+    }
+  }
+
+  //Errors that leave the socket usable are only logged. A connector's socket is connected, so a
+  //refusal can only come from the peer being connected to. A listener can't tell which peer an
+  //ICMP error was about; its sessions time out instead.
+  fn handle_recv_error(&mut self, e: io::Error) {
+    debug!("ignoring socket error: {}", e);
+    if e.kind() != ErrorKind::ConnectionRefused || self.accepted.is_some() {
+      return;
+    }
+    let now = Instant::now();
+    for (address, reply) in self.connects.drain().collect::<Vec<_>>() {
+      let _ = reply.try_send(Err(io::Error::new(e.kind(), e.to_string()).into()));
+      self.endpoint.close(address, now);
+    }
+  }
+
+  //Pending connects fail with the error, and connections see `DisconnectReason::Closed`.
+  fn fail(&mut self, e: io::Error) -> io::Error {
+    error!("socket failed: {}", e);
+    for (_, reply) in self.connects.drain() {
+      let _ = reply.try_send(Err(io::Error::new(e.kind(), e.to_string()).into()));
+    }
+    for (_, remote) in self.connections.drain() {
+      remote.deliver(Event::Disconnected(DisconnectReason::Closed));
+    }
+    e
+  }
+
+  async fn flush(&mut self) -> io::Result<()> {
+    //Oversized MTU probes are replaced by smaller ones, which are sent right away.
+    let mut transmits: Vec<_> = iter::from_fn(|| self.endpoint.poll_transmit()).collect();
+    while !transmits.is_empty() {
+      self.io.send(&transmits).await?;
+      let now = Instant::now();
+      for (destination, size) in self.io.oversized() {
+        self.endpoint.handle_oversized(destination, size, now);
+      }
+      transmits = iter::from_fn(|| self.endpoint.poll_transmit()).collect();
+    }
+    while let Some(event) = self.endpoint.poll_event() {
+      match event {
+        EndpointEvent::Accepted { address, session, mtu_size } => {
+          let (connection, remote) = Connection::pair(address, session, mtu_size, self.endpoint.config(), self.commands_sender.clone());
+          if self.accepted.as_ref().is_some_and(|accepted| accepted.try_send(connection).is_ok()) {
+            self.connections.insert(address, remote);
+          }
+          else {
+            //Nobody could ever close it otherwise, and pings would keep it alive.
+            self.endpoint.close(address, Instant::now());
+          }
+        },
+        EndpointEvent::Connected { address, session, mtu_size } => {
+          let (connection, remote) = Connection::pair(address, session, mtu_size, self.endpoint.config(), self.commands_sender.clone());
+          if self.connects.remove(&address).is_some_and(|reply| reply.try_send(Ok(connection)).is_ok()) {
+            self.connections.insert(address, remote);
+          }
+          else {
+            //The connect was given up, e.g. under a timeout.
+            self.endpoint.close(address, Instant::now());
+          }
+        },
+        EndpointEvent::ConnectFailed { address, error } => {
+          if let Some(reply) = self.connects.remove(&address) {
+            let _ = reply.try_send(Err(error));
+          }
+        },
+        EndpointEvent::Message { address, payload } => {
+          if let Some(remote) = self.connections.get(&address) {
+            remote.deliver(Event::Message(Message { payload }));
+          }
+        },
+        EndpointEvent::Disconnected { address, reason } => {
+          if let Some(remote) = self.connections.remove(&address) {
+            remote.deliver(Event::Disconnected(reason));
+          }
+        },
+      }
+    }
+    for (address, remote) in &self.connections {
+      remote.set_rtt(self.endpoint.rtt(*address));
+    }
+    Ok(())
+  }
+}
@@ -0,0 +1,789 @@
+use crate::socket::SocketConfiguration;
+use crate::admission::{Admission, BanList, IpNetwork, Rejection};
+use crate::advertisement::{Advertisement, PingRequest};
+use crate::codable::{self, Codable};
+use crate::connection::DisconnectReason;
+use crate::connector::ConnectError;
+use crate::constants::{PacketReliability, PacketPriority, RAKNET_PROTOCOL_VERSION, OFFLINE_MAGIC, MINIMUM_MTU_SIZE, MTU_PROBE_SIZES};
+use crate::protocol::PacketIdentifiers;
+use crate::protocol::ping::{UnconnectedPing, UnconnectedPong};
+use crate::protocol::open::{OpenConnectionRequest1, OpenConnectionReply1, OpenConnectionRequest2, OpenConnectionReply2};
+use crate::protocol::disconnect::IncompatibleProtocolVersion;
+use crate::protocol::version;
+use crate::session::{Session, SessionEvent, SessionError, SessionState};
+use crate::types::{RakString, SystemAddress};
+
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::net::{IpAddr, SocketAddr};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use num_traits::FromPrimitive;
+
+//How often sessions flush ACKs, retransmit and check their timers.
+pub const UPDATE_INTERVAL: Duration = Duration::from_millis(10);
+
+pub struct Transmit {
+  pub destination: SocketAddr,
+  pub payload: Vec<u8>,
+}
+
+pub enum EndpointEvent {
+  //A peer completed the handshake with a server endpoint. `session` is the session's id.
+  Accepted { address: SocketAddr, session: u64, mtu_size: u16 },
+  //A handshake started with `connect` completed.
+  Connected { address: SocketAddr, session: u64, mtu_size: u16 },
+  ConnectFailed { address: SocketAddr, error: ConnectError },
+  Message { address: SocketAddr, payload: Vec<u8> },
+  //Only raised for peers that were Accepted or Connected before.
+  Disconnected { address: SocketAddr, reason: DisconnectReason },
+}
+
+//The offline part of a client handshake, before the session exists.
+struct Handshake {
+  //Versions left to try, the current one is `protocol`.
+  protocols: VecDeque<u8>,
+  protocol: u8,
+  stage: Stage,
+  attempts: usize,
+  next_send: Instant,
+}
+
+enum Stage {
+  //MTU sizes left to probe, the first one is being probed.
+  Request1 { mtu_sizes: VecDeque<u16> },
+  Request2 { mtu_size: u16 },
+}
+
+//The protocol machinery without any IO: packets are passed in with the time they arrived,
+//and datagrams, timers and events are polled out.
+//A server endpoint also answers pings and accepts handshakes; any endpoint can connect.
+pub struct Endpoint {
+  config: SocketConfiguration,
+  server: bool,
+  //Cleared by `close_all`, after which handshakes from new peers are ignored.
+  accepting: bool,
+  guid: u64,
+  epoch: Instant,
+  next_update: Instant,
+  admission: Admission,
+  advertisement: Arc<Mutex<Advertisement>>,
+  //Versions from OpenConnectionRequest1, until the session is created.
+  protocols: HashMap<SocketAddr, (u8, Instant)>,
+  handshakes: HashMap<SocketAddr, Handshake>,
+  //Client sessions that have not connected yet, with their deadline.
+  connecting: HashMap<SocketAddr, Instant>,
+  sessions: HashMap<SocketAddr, Session>,
+  next_session_id: u64,
+  established: HashSet<SocketAddr>,
+  transmits: VecDeque<Transmit>,
+  events: VecDeque<EndpointEvent>,
+}
+
+impl Endpoint {
+  pub fn server(config: SocketConfiguration, now: Instant) -> Endpoint {
+    let mut endpoint = Self::client(config, now);
+    endpoint.server = true;
+    endpoint
+  }
+
+  pub fn client(config: SocketConfiguration, now: Instant) -> Endpoint {
+    Endpoint {
+      admission: Admission::new(&config, Arc::new(Mutex::new(BanList::new()))),
+      config,
+      server: false,
+      accepting: true,
+      guid: config.guid.unwrap_or_else(rand::random),
+      epoch: now,
+      next_update: now + UPDATE_INTERVAL,
+      advertisement: Arc::new(Mutex::new(Advertisement::default())),
+      protocols: HashMap::new(),
+      handshakes: HashMap::new(),
+      connecting: HashMap::new(),
+      sessions: HashMap::new(),
+      next_session_id: 0,
+      established: HashSet::new(),
+      transmits: VecDeque::new(),
+      events: VecDeque::new(),
+    }
+  }
+
+  //Sent as server id in offline replies and as client id in handshakes.
+  pub fn guid(&self) -> u64 {
+    self.guid
+  }
+
+  pub fn set_guid(&mut self, guid: u64) {
+    self.guid = guid;
+  }
+
+  pub fn config(&self) -> &SocketConfiguration {
+    &self.config
+  }
+
+  pub fn set_advertisement(&self, information: impl Into<String>) {
+    *self.advertisement.lock().unwrap() = Advertisement::Static(information.into());
+  }
+
+  pub fn set_advertiser(&self, advertiser: impl Fn(&PingRequest) -> String + Send + 'static) {
+    *self.advertisement.lock().unwrap() = Advertisement::Dynamic(Box::new(advertiser));
+  }
+
+  pub fn ban(&self, network: IpNetwork, duration: Option<Duration>, now: Instant) {
+    self.admission.bans().lock().unwrap().ban(network, duration, now);
+  }
+
+  pub fn unban(&self, network: IpNetwork) -> bool {
+    self.admission.bans().lock().unwrap().unban(network)
+  }
+
+  pub fn is_banned(&self, address: IpAddr, now: Instant) -> bool {
+    self.admission.is_banned(address, now)
+  }
+
+  //Shared with handles that change them from other threads.
+  pub(crate) fn shared(&self) -> (Arc<Mutex<BanList>>, Arc<Mutex<Advertisement>>) {
+    (self.admission.bans().clone(), self.advertisement.clone())
+  }
+
+  pub fn session(&self, address: SocketAddr) -> Option<&Session> {
+    self.sessions.get(&address)
+  }
+
+  pub fn rtt(&self, address: SocketAddr) -> Option<Duration> {
+    self.sessions.get(&address).and_then(Session::rtt)
+  }
+
+  //No session or handshake is left, so no timer is pending.
+  pub fn is_idle(&self) -> bool {
+    self.sessions.is_empty() && self.handshakes.is_empty()
+  }
+
+  //When `handle_timeout` should be called next, None while idle. Versions remembered from
+  //OpenConnectionRequest1 keep the timer running too, so that they expire.
+  pub fn poll_timeout(&self) -> Option<Instant> {
+    if self.is_idle() && self.protocols.is_empty() { None } else { Some(self.next_update) }
+  }
+
+  pub fn poll_transmit(&mut self) -> Option<Transmit> {
+    self.transmits.pop_front()
+  }
+
+  pub fn poll_event(&mut self) -> Option<EndpointEvent> {
+    self.events.pop_front()
+  }
+
+  //Starts the handshake with a server. Completion is reported with Connected or ConnectFailed.
+  pub fn connect(&mut self, address: SocketAddr, now: Instant) -> Result<(), ConnectError> {
+    if self.sessions.contains_key(&address) || self.handshakes.contains_key(&address) {
+      return Err(ConnectError::AlreadyConnected);
+    }
+    let mut protocols: VecDeque<u8> = self.config.protocol_versions.iter().copied().collect();
+    let protocol = protocols.pop_front().ok_or(ConnectError::IncompatibleProtocolVersion { protocol: RAKNET_PROTOCOL_VERSION })?;
+    let handshake = Handshake {
+      protocols,
+      protocol,
+      stage: Stage::Request1 { mtu_sizes: self.mtu_sizes() },
+      attempts: 0,
+      next_send: now,
+    };
+    self.handshakes.insert(address, handshake);
+    self.connecting.insert(address, now + self.config.connect_timeout);
+    self.update_handshake(address, now);
+    Ok(())
+  }
+
+  pub fn send(&mut self, address: SocketAddr, payload: Vec<u8>, reliability: PacketReliability, priority: PacketPriority, channel: u8, now: Instant) -> Result<(), SessionError> {
+    let session = self.sessions.get_mut(&address).ok_or(SessionError::NotConnected(SessionState::Unconnected))?;
+    let result = session.send(payload, reliability, priority, channel, now);
+    self.drain(address);
+    result
+  }
+
+  //Gracefully closes the session with `address`, or abandons its handshake.
+  pub fn close(&mut self, address: SocketAddr, now: Instant) {
+    if self.handshakes.remove(&address).is_some() {
+      self.fail(address, ConnectError::Disconnected(DisconnectReason::Closed));
+    }
+    if let Some(session) = self.sessions.get_mut(&address) {
+      session.close(now);
+    }
+    self.drain(address);
+  }
+
+  //Closes every session and handshake, and stops accepting new ones.
+  pub fn close_all(&mut self, now: Instant) {
+    self.accepting = false;
+    let addresses: Vec<SocketAddr> = self.sessions.keys().chain(self.handshakes.keys()).copied().collect();
+    for address in addresses {
+      self.close(address, now);
+    }
+  }
+
+  //Handles one received UDP payload.
+  pub fn handle(&mut self, address: SocketAddr, buffer: &[u8], now: Instant) {
+    let id = match buffer.first() {
+      Some(id) => *id,
+      None => return,
+    };
+    if id & PacketIdentifiers::DatagramValid as u8 != 0 {
+      if let Some(session) = self.sessions.get_mut(&address) {
+        //Out-of-order handshake packets are rejected by the session and dropped here.
+        let _ = session.handle_packet(buffer, now);
+      }
+      self.drain(address);
+      return;
+    }
+    if self.handshakes.contains_key(&address) {
+      self.handle_handshake_reply(address, buffer, now);
+      return;
+    }
+    if !self.server {
+      return;
+    }
+    let reply = match PacketIdentifiers::from_u8(id) {
+      Some(PacketIdentifiers::UnconnectedPing) => self.handle_unconnected_ping(address, buffer),
+      //Only answered while a new peer could connect, so browsers can skip full servers.
+      Some(PacketIdentifiers::UnconnectedPingOpenConnection) if self.accepting && self.admission.has_free_slots(self.sessions.len()) => {
+        self.handle_unconnected_ping(address, buffer)
+      },
+      Some(PacketIdentifiers::OpenConnectionRequest1) if self.accepting => self.handle_open_connection_request1(address, buffer, now),
+      Some(PacketIdentifiers::OpenConnectionRequest2) if self.accepting => self.handle_open_connection_request2(address, buffer, now),
+      _ => return,
+    };
+    //Malformed offline packets are dropped without a reply.
+    if let Ok(Some(payload)) = reply {
+      self.transmits.push_back(Transmit { destination: address, payload });
+    }
+  }
+
+  //A datagram of `size` bytes to `address` was refused as larger than the path MTU. If it was an
+  //MTU probe, that size and every larger one are skipped right away.
+  pub fn handle_oversized(&mut self, address: SocketAddr, size: usize, now: Instant) {
+    let handshake = match self.handshakes.get_mut(&address) {
+      Some(handshake) => handshake,
+      None => return,
+    };
+    let mtu_sizes = match &mut handshake.stage {
+      Stage::Request1 { mtu_sizes } => mtu_sizes,
+      Stage::Request2 { .. } => return,
+    };
+    let mtu_size = version::request1_mtu_size(size);
+    if mtu_sizes.front().is_none_or(|probed| *probed < mtu_size) {
+      return;
+    }
+    while mtu_sizes.front().is_some_and(|probed| *probed >= mtu_size) {
+      mtu_sizes.pop_front();
+    }
+    handshake.attempts = 0;
+    handshake.next_send = now;
+    self.update_handshake(address, now);
+  }
+
+  //Updates every session and handshake. Due once `poll_timeout` has passed.
+  pub fn handle_timeout(&mut self, now: Instant) {
+    self.admission.update(now);
+    let timeout = self.config.timeout;
+    self.protocols.retain(|_, (_, received)| now.saturating_duration_since(*received) < timeout);
+    let handshakes: Vec<SocketAddr> = self.handshakes.keys().copied().collect();
+    for address in handshakes {
+      self.update_handshake(address, now);
+    }
+    let expired: Vec<SocketAddr> = self.connecting.iter()
+      .filter(|(address, deadline)| now >= **deadline && self.sessions.contains_key(address))
+      .map(|(address, _)| *address)
+      .collect();
+    for address in expired {
+      self.sessions.remove(&address);
+      self.fail(address, ConnectError::Timeout);
+    }
+    let addresses: Vec<SocketAddr> = self.sessions.keys().copied().collect();
+    for address in addresses {
+      if let Some(session) = self.sessions.get_mut(&address) {
+        let _ = session.update(now);
+      }
+      self.drain(address);
+    }
+    self.next_update = now + UPDATE_INTERVAL;
+  }
+
+  fn handle_unconnected_ping(&self, address: SocketAddr, buffer: &[u8]) -> codable::Result<Option<Vec<u8>>> {
+    let ping = UnconnectedPing::from_bytes(buffer)?;
+    if ping.offline_magic != OFFLINE_MAGIC {
+      return Ok(None);
+    }
+    let request = PingRequest {
+      address,
+      connections: self.established.len(),
+    };
+    let information = self.advertisement.lock().unwrap().build(&request);
+    let pong = UnconnectedPong {
+      id: PacketIdentifiers::UnconnectedPong as u8,
+      ping_time: ping.ping_time,
+      server_id: self.guid,
+      offline_magic: OFFLINE_MAGIC,
+      information: RakString(information),
+    };
+    pong.to_bytes().map(Some)
+  }
+
+  fn handle_open_connection_request1(&mut self, address: SocketAddr, buffer: &[u8], now: Instant) -> codable::Result<Option<Vec<u8>>> {
+    let request = OpenConnectionRequest1::from_bytes(buffer)?;
+    if request.offline_magic != OFFLINE_MAGIC {
+      return Ok(None);
+    }
+    if self.admission.is_banned(address.ip(), now) {
+      return Rejection::Banned.to_bytes(self.guid).map(Some);
+    }
+    if !self.config.protocol_versions.contains(&request.protocol) {
+      let reply = IncompatibleProtocolVersion {
+        id: PacketIdentifiers::IncompatibleProtocolVersion as u8,
+        protocol: self.config.protocol_versions.first().copied().unwrap_or(RAKNET_PROTOCOL_VERSION),
+        offline_magic: OFFLINE_MAGIC,
+        server_id: self.guid,
+      };
+      return reply.to_bytes().map(Some);
+    }
+    //OpenConnectionRequest2 does not repeat the version.
+    self.protocols.insert(address, (request.protocol, now));
+    let reply = OpenConnectionReply1 {
+      id: PacketIdentifiers::OpenConnectionReply1 as u8,
+      offline_magic: OFFLINE_MAGIC,
+      server_id: self.guid,
+      security: false,
+      mtu_size: clamp_mtu_size(request.mtu_size, &self.config),
+    };
+    reply.to_bytes().map(Some)
+  }
+
+  fn handle_open_connection_request2(&mut self, address: SocketAddr, buffer: &[u8], now: Instant) -> codable::Result<Option<Vec<u8>>> {
+    let request = OpenConnectionRequest2::from_bytes(buffer)?;
+    if request.offline_magic != OFFLINE_MAGIC {
+      return Ok(None);
+    }
+    //A repeated request for an open session is answered again, any later one is a duplicate handshake.
+    if let Some(session) = self.sessions.get_mut(&address) {
+      return match session.open() {
+        Ok(reply) => reply.to_bytes().map(Some),
+        Err(_) => Rejection::AlreadyConnected.to_bytes(self.guid).map(Some),
+      };
+    }
+    let protocol = match self.protocols.get(&address) {
+      Some((protocol, _)) => *protocol,
+      None => return Ok(None),
+    };
+    if let Err(rejection) = self.admission.admit(address.ip(), self.sessions.len(), now) {
+      return rejection.to_bytes(self.guid).map(Some);
+    }
+    self.protocols.remove(&address);
+    let mtu_size = clamp_mtu_size(request.mtu_size, &self.config);
+    let mut session = Session::new(&self.config, address, self.guid, request.client_id, mtu_size, protocol, self.epoch);
+    let reply = session.open();
+    self.insert_session(address, session);
+    match reply {
+      Ok(reply) => reply.to_bytes().map(Some),
+      Err(_) => Ok(None),
+    }
+  }
+
+  fn handle_handshake_reply(&mut self, address: SocketAddr, buffer: &[u8], now: Instant) {
+    let mtu_sizes = self.mtu_sizes();
+    let handshake = match self.handshakes.get_mut(&address) {
+      Some(handshake) => handshake,
+      None => return,
+    };
+    match (&handshake.stage, PacketIdentifiers::from_u8(buffer[0])) {
+      (Stage::Request1 { .. }, Some(PacketIdentifiers::OpenConnectionReply1)) => {
+        let reply = match OpenConnectionReply1::from_bytes(buffer) {
+          Ok(reply) if is_valid_mtu_size(reply.mtu_size, &self.config) => reply,
+          _ => return,
+        };
+        handshake.stage = Stage::Request2 { mtu_size: reply.mtu_size };
+        handshake.attempts = 0;
+        handshake.next_send = now;
+        self.update_handshake(address, now);
+      },
+      (Stage::Request2 { mtu_size }, Some(PacketIdentifiers::OpenConnectionReply2)) => {
+        let reply = match OpenConnectionReply2::from_bytes(buffer) {
+          Ok(reply) if is_valid_mtu_size(reply.mtu_size, &self.config) => reply,
+          _ => return,
+        };
+        let (mtu_size, protocol) = (reply.mtu_size.min(*mtu_size), handshake.protocol);
+        self.handshakes.remove(&address);
+        let mut session = Session::client(&self.config, address, reply.server_id, self.guid, mtu_size, protocol, self.epoch);
+        match session.connect(now) {
+          Ok(()) => {
+            self.insert_session(address, session);
+            self.drain(address);
+          },
+          Err(e) => self.fail(address, e.into()),
+        }
+      },
+      //If the server prefers a version that is also configured, that one is tried next.
+      (Stage::Request1 { .. }, Some(PacketIdentifiers::IncompatibleProtocolVersion)) => {
+        let preferred = match IncompatibleProtocolVersion::from_bytes(buffer) {
+          Ok(reply) => reply.protocol,
+          Err(_) => return,
+        };
+        if let Some(index) = handshake.protocols.iter().position(|protocol| *protocol == preferred) {
+          handshake.protocols.remove(index);
+          handshake.protocols.push_front(preferred);
+        }
+        match handshake.protocols.pop_front() {
+          Some(protocol) => {
+            handshake.protocol = protocol;
+            handshake.stage = Stage::Request1 { mtu_sizes };
+            handshake.attempts = 0;
+            handshake.next_send = now;
+            self.update_handshake(address, now);
+          },
+          None => {
+            self.handshakes.remove(&address);
+            self.fail(address, ConnectError::IncompatibleProtocolVersion { protocol: preferred });
+          },
+        }
+      },
+      _ => if let Some(rejection) = ConnectError::from_packet(buffer) {
+        self.handshakes.remove(&address);
+        self.fail(address, rejection);
+      },
+    }
+  }
+
+  //Sends the current request when due. Each MTU size is probed `mtu_probe_attempts` times,
+  //requests larger than the path MTU are dropped on the way and time out.
+  fn update_handshake(&mut self, address: SocketAddr, now: Instant) {
+    let handshake = match self.handshakes.get_mut(&address) {
+      Some(handshake) => handshake,
+      None => return,
+    };
+    if now < handshake.next_send {
+      return;
+    }
+    let exhausted = handshake.attempts >= self.config.mtu_probe_attempts;
+    let request = match &mut handshake.stage {
+      Stage::Request1 { mtu_sizes } => {
+        if exhausted {
+          mtu_sizes.pop_front();
+          handshake.attempts = 0;
+        }
+        mtu_sizes.front().map(|mtu_size| OpenConnectionRequest1 {
+          id: PacketIdentifiers::OpenConnectionRequest1 as u8,
+          offline_magic: OFFLINE_MAGIC,
+          protocol: handshake.protocol,
+          mtu_size: *mtu_size,
+        }.to_bytes())
+      },
+      Stage::Request2 { mtu_size } => (!exhausted).then(|| OpenConnectionRequest2 {
+        id: PacketIdentifiers::OpenConnectionRequest2 as u8,
+        offline_magic: OFFLINE_MAGIC,
+        server_address: SystemAddress(address),
+        mtu_size: *mtu_size,
+        client_id: self.guid,
+      }.to_bytes()),
+    };
+    match request {
+      Some(Ok(payload)) => {
+        handshake.attempts += 1;
+        handshake.next_send = now + self.config.mtu_probe_timeout;
+        self.transmits.push_back(Transmit { destination: address, payload });
+      },
+      Some(Err(e)) => {
+        self.handshakes.remove(&address);
+        self.fail(address, SessionError::from(e).into());
+      },
+      None => {
+        self.handshakes.remove(&address);
+        self.fail(address, ConnectError::Timeout);
+      },
+    }
+  }
+
+  fn insert_session(&mut self, address: SocketAddr, mut session: Session) {
+    session.set_id(self.next_session_id);
+    self.next_session_id += 1;
+    self.sessions.insert(address, session);
+  }
+
+  fn mtu_sizes(&self) -> VecDeque<u16> {
+    MTU_PROBE_SIZES.iter().copied().filter(|mtu_size| *mtu_size <= self.config.max_mtu_size).collect()
+  }
+
+  fn fail(&mut self, address: SocketAddr, error: ConnectError) {
+    self.connecting.remove(&address);
+    self.events.push_back(EndpointEvent::ConnectFailed { address, error });
+  }
+
+  //Moves the datagrams and events of a session out, and removes it once disconnected.
+  fn drain(&mut self, address: SocketAddr) {
+    let session = match self.sessions.get_mut(&address) {
+      Some(session) => session,
+      None => return,
+    };
+    while let Some(payload) = session.poll_transmit() {
+      self.transmits.push_back(Transmit { destination: address, payload });
+    }
+    let mut disconnected = None;
+    let mut rejected = None;
+    while let Some(event) = session.poll_event() {
+      match event {
+        SessionEvent::Connected => {
+          let (session, mtu_size) = (session.id(), session.mtu_size());
+          self.established.insert(address);
+          if self.connecting.remove(&address).is_some() {
+            self.events.push_back(EndpointEvent::Connected { address, session, mtu_size });
+          }
+          else {
+            self.events.push_back(EndpointEvent::Accepted { address, session, mtu_size });
+          }
+        },
+        SessionEvent::Message(payload) => self.events.push_back(EndpointEvent::Message { address, payload }),
+        SessionEvent::Rejected(id) => rejected = ConnectError::from_id(id),
+        SessionEvent::Disconnected(reason) => disconnected = Some(reason),
+      }
+    }
+    if let Some(rejection) = rejected {
+      self.sessions.remove(&address);
+      self.fail(address, rejection);
+    }
+    else if let Some(reason) = disconnected {
+      self.sessions.remove(&address);
+      if self.established.remove(&address) {
+        self.events.push_back(EndpointEvent::Disconnected { address, reason });
+      }
+      else if self.connecting.contains_key(&address) {
+        let error = match reason {
+          DisconnectReason::Timeout => ConnectError::Timeout,
+          reason => ConnectError::Disconnected(reason),
+        };
+        self.fail(address, error);
+      }
+    }
+  }
+}
+
+fn max_mtu_size(config: &SocketConfiguration) -> u16 {
+  let max = config.max_mtu_size.min(config.recv_buffer_size.min(u16::MAX as usize) as u16);
+  max.max(MINIMUM_MTU_SIZE)
+}
+
+fn clamp_mtu_size(mtu_size: u16, config: &SocketConfiguration) -> u16 {
+  mtu_size.clamp(MINIMUM_MTU_SIZE, max_mtu_size(config))
+}
+
+//A server picks its MTU size with `clamp_mtu_size`, so replies outside that range are bogus.
+fn is_valid_mtu_size(mtu_size: u16, config: &SocketConfiguration) -> bool {
+  (MINIMUM_MTU_SIZE..=max_mtu_size(config)).contains(&mtu_size)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  const SERVER: &str = "10.0.0.1:19132";
+
+  //A server and clients exchanging datagrams in memory, with a simulated clock.
+  struct Network {
+    server: Endpoint,
+    clients: Vec<(SocketAddr, Endpoint)>,
+    now: Instant,
+    connected: bool,
+    server_events: Vec<EndpointEvent>,
+    client_events: Vec<Vec<EndpointEvent>>,
+  }
+
+  impl Network {
+    fn new(server: SocketConfiguration) -> Network {
+      let now = Instant::now();
+      Network {
+        server: Endpoint::server(server, now),
+        clients: Vec::new(),
+        now,
+        connected: true,
+        server_events: Vec::new(),
+        client_events: Vec::new(),
+      }
+    }
+
+    fn connect(&mut self, config: SocketConfiguration) -> usize {
+      let address = SocketAddr::new([10, 0, 1, self.clients.len() as u8 + 1].into(), 50000);
+      let mut client = Endpoint::client(config, self.now);
+      client.connect(SERVER.parse().unwrap(), self.now).unwrap();
+      self.clients.push((address, client));
+      self.client_events.push(Vec::new());
+      self.clients.len() - 1
+    }
+
+    fn step(&mut self) {
+      let server_address: SocketAddr = SERVER.parse().unwrap();
+      self.now += UPDATE_INTERVAL;
+      for (address, client) in &mut self.clients {
+        while let Some(transmit) = client.poll_transmit() {
+          if self.connected {
+            self.server.handle(*address, &transmit.payload, self.now);
+          }
+        }
+      }
+      while let Some(transmit) = self.server.poll_transmit() {
+        if let Some((_, client)) = self.clients.iter_mut().find(|(address, _)| *address == transmit.destination) {
+          if self.connected {
+            client.handle(server_address, &transmit.payload, self.now);
+          }
+        }
+      }
+      self.server.handle_timeout(self.now);
+      self.server_events.extend(drain_events(&mut self.server));
+      for ((_, client), events) in self.clients.iter_mut().zip(&mut self.client_events) {
+        client.handle_timeout(self.now);
+        events.extend(drain_events(client));
+      }
+    }
+
+    fn run(&mut self, duration: Duration) {
+      let end = self.now + duration;
+      while self.now < end {
+        self.step();
+      }
+    }
+  }
+
+  fn drain_events(endpoint: &mut Endpoint) -> Vec<EndpointEvent> {
+    std::iter::from_fn(|| endpoint.poll_event()).collect()
+  }
+
+  fn config() -> SocketConfiguration {
+    SocketConfiguration::builder()
+      .ping_interval(Duration::from_millis(200))
+      .timeout(Duration::from_secs(1))
+      .build()
+      .unwrap()
+  }
+
+  #[test]
+  fn handshake() {
+    let mut network = Network::new(config());
+    let client = network.connect(SocketConfiguration::builder().max_mtu_size(1200).build().unwrap());
+    network.run(Duration::from_millis(200));
+    assert!(matches!(network.client_events[client][..], [EndpointEvent::Connected { mtu_size: 1200, .. }]));
+    assert!(matches!(network.server_events[..], [EndpointEvent::Accepted { mtu_size: 1200, .. }]));
+    let address = network.clients[client].0;
+    network.clients[client].1.send(SERVER.parse().unwrap(), vec![0x90, 1, 2], PacketReliability::ReliableOrdered, PacketPriority::Medium, 0, network.now).unwrap();
+    network.run(Duration::from_millis(100));
+    match &network.server_events[1] {
+      EndpointEvent::Message { address: from, payload } => assert_eq!((*from, &payload[..]), (address, &[0x90, 1, 2][..])),
+      _ => panic!("expected a message"),
+    }
+  }
+
+  #[test]
+  fn out_of_range_mtu_replies_are_ignored() {
+    let server: SocketAddr = SERVER.parse().unwrap();
+    let mut now = Instant::now();
+    let mut client = Endpoint::client(SocketConfiguration::builder().max_mtu_size(1200).build().unwrap(), now);
+    client.connect(server, now).unwrap();
+    let reply = |mtu_size| OpenConnectionReply1 {
+      id: PacketIdentifiers::OpenConnectionReply1 as u8,
+      offline_magic: OFFLINE_MAGIC,
+      server_id: 1,
+      security: false,
+      mtu_size,
+    }.to_bytes().unwrap();
+    let next_request = |client: &mut Endpoint, now: &mut Instant| {
+      *now += Duration::from_secs(1);
+      client.handle_timeout(*now);
+      let id = std::iter::from_fn(|| client.poll_transmit()).last().unwrap().payload[0];
+      PacketIdentifiers::from_u8(id)
+    };
+    for mtu_size in [MINIMUM_MTU_SIZE - 1, 1201] {
+      client.handle(server, &reply(mtu_size), now);
+      assert_eq!(next_request(&mut client, &mut now), Some(PacketIdentifiers::OpenConnectionRequest1));
+    }
+    client.handle(server, &reply(1200), now);
+    assert_eq!(next_request(&mut client, &mut now), Some(PacketIdentifiers::OpenConnectionRequest2));
+  }
+
+  #[test]
+  fn version_fallback() {
+    let mut network = Network::new(SocketConfiguration::builder().protocol_versions(&[10]).build().unwrap());
+    let client = network.connect(SocketConfiguration::builder().protocol_versions(&[11, 10]).build().unwrap());
+    let incompatible = network.connect(SocketConfiguration::builder().protocol_versions(&[11]).build().unwrap());
+    network.run(Duration::from_millis(200));
+    assert!(matches!(network.client_events[client][..], [EndpointEvent::Connected { .. }]));
+    assert!(matches!(network.client_events[incompatible][..], [EndpointEvent::ConnectFailed {
+      error: ConnectError::IncompatibleProtocolVersion { protocol: 10 }, ..
+    }]));
+    assert_eq!(network.server.session(network.clients[client].0).map(Session::protocol), Some(10));
+  }
+
+  #[test]
+  fn admission_rejections() {
+    let mut network = Network::new(SocketConfiguration::builder().max_connections(1).build().unwrap());
+    let first = network.connect(config());
+    network.run(Duration::from_millis(200));
+    let full = network.connect(config());
+    network.run(Duration::from_millis(200));
+    assert!(matches!(network.client_events[first][..], [EndpointEvent::Connected { .. }]));
+    assert!(matches!(network.client_events[full][..], [EndpointEvent::ConnectFailed { error: ConnectError::NoFreeIncomingConnections, .. }]));
+
+    network.server.ban("10.0.1.3/32".parse().unwrap(), None, network.now);
+    let banned = network.connect(config());
+    network.run(Duration::from_millis(200));
+    assert!(matches!(network.client_events[banned][..], [EndpointEvent::ConnectFailed { error: ConnectError::ConnectionBanned, .. }]));
+  }
+
+  #[test]
+  fn timeout() {
+    let mut network = Network::new(config());
+    let client = network.connect(config());
+    network.run(Duration::from_millis(200));
+    network.connected = false;
+    network.run(Duration::from_millis(1200));
+    assert!(matches!(network.client_events[client][..], [
+      EndpointEvent::Connected { .. },
+      EndpointEvent::Disconnected { reason: DisconnectReason::Timeout, .. },
+    ]));
+    assert!(matches!(network.server_events[..], [
+      EndpointEvent::Accepted { .. },
+      EndpointEvent::Disconnected { reason: DisconnectReason::Timeout, .. },
+    ]));
+    assert!(network.server.is_idle() && network.clients[client].1.is_idle());
+  }
+
+  #[test]
+  fn close_all_stops_accepting() {
+    let mut network = Network::new(config());
+    let client = network.connect(config());
+    network.run(Duration::from_millis(200));
+    network.server.close_all(network.now);
+    let late = network.connect(config());
+    network.run(Duration::from_millis(300));
+    assert!(matches!(network.client_events[client][..], [
+      EndpointEvent::Connected { .. },
+      EndpointEvent::Disconnected { reason: DisconnectReason::Remote, .. },
+    ]));
+    assert!(network.client_events[late].is_empty());
+    assert!(network.server.is_idle());
+  }
+
+  #[test]
+  fn graceful_close() {
+    let mut network = Network::new(config());
+    let client = network.connect(config());
+    network.run(Duration::from_millis(200));
+    let server_address = SERVER.parse().unwrap();
+    let endpoint = &mut network.clients[client].1;
+    endpoint.send(server_address, vec![0x90; 4000], PacketReliability::ReliableOrdered, PacketPriority::Medium, 0, network.now).unwrap();
+    endpoint.close(server_address, network.now);
+    network.run(Duration::from_millis(200));
+    assert!(matches!(network.client_events[client][..], [
+      EndpointEvent::Connected { .. },
+      EndpointEvent::Disconnected { reason: DisconnectReason::Closed, .. },
+    ]));
+    match &network.server_events[..] {
+      [EndpointEvent::Accepted { .. }, EndpointEvent::Message { payload, .. }, EndpointEvent::Disconnected { reason: DisconnectReason::Remote, .. }] => {
+        assert_eq!(payload.len(), 4000);
+      },
+      _ => panic!("expected the message before the disconnect"),
+    }
+    assert!(network.server.is_idle());
+  }
+}
@@ -8,5 +8,15 @@ pub mod codable;
 pub mod types;
 pub mod constants;
 pub mod protocol;
+pub mod session;
 pub mod socket;
+pub mod runtime;
+pub mod endpoint;
+pub mod admission;
+pub mod advertisement;
 pub mod listener;
+pub mod connection;
+pub mod connector;
+mod driver;
+mod udp;
+pub mod discovery;
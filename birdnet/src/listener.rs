@@ -1,67 +1,140 @@
 use crate::socket::SocketConfiguration;
+use crate::admission::{BanList, IpNetwork};
+use crate::advertisement::{Advertisement, PingRequest};
+use crate::endpoint::Endpoint;
+use crate::driver::Driver;
+use crate::connection::Connection;
+use crate::runtime::{self, UdpSocket};
 
-use std::time::Duration;
-use std::sync::atomic::{AtomicBool, Ordering};
-use async_std::io::{self, ErrorKind};
-use async_std::net::{UdpSocket, SocketAddr};
-use async_std::sync::{Arc};
-use async_std::channel::{bounded, Sender, Receiver};
-use async_std::task::{self, JoinHandle};
+use std::io;
+use std::time::{Duration, Instant};
+use std::sync::Mutex;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use async_channel::{unbounded, bounded, Sender, Receiver};
+use futures_lite::Stream;
+use log::warn;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ListenerStatus {
+  Running,
+  //Shut down, or dropped.
+  Closed,
+  //The socket returned an error that can't be recovered from. Connections were closed.
+  Failed(io::ErrorKind),
+}
 
 pub struct Listener {
-  shutdown: Arc<AtomicBool>,
-  recv_task: Option<JoinHandle<()>>,
+  //Closing it shuts the receiving task down, which then drops `stopped`.
+  shutdown: Sender<()>,
+  stopped: Receiver<()>,
+  status: Arc<Mutex<ListenerStatus>>,
+  incoming: Receiver<Connection>,
+  bans: Arc<Mutex<BanList>>,
+  advertisement: Arc<Mutex<Advertisement>>,
+  server_id: u64,
 }
 
 impl Listener {
   pub fn new(socket: Arc<UdpSocket>) -> Listener {
-    Self::with_configuration(socket, SocketConfiguration {
-      recv_buffer_size: 4096,
-    })
+    Self::with_configuration(socket, Default::default())
   }
 
   pub fn with_configuration(socket: Arc<UdpSocket>, config: SocketConfiguration) -> Listener {
-    let shutdown = Arc::new(AtomicBool::new(false));
-    let recv_task = Some(task::spawn(receiver(config, ReceiverContext {
-      socket,
-      shutdown: shutdown.clone(),
-    })));
+    Self::with_endpoint(socket, Endpoint::server(config, Instant::now()))
+  }
+
+  //Binds a socket with the configured socket options and listens on it.
+  pub fn bind(address: SocketAddr, config: SocketConfiguration) -> io::Result<Listener> {
+    Ok(Self::with_configuration(Arc::new(config.bind(address)?), config))
+  }
+
+  //Drives an endpoint that was set up beforehand, e.g. with a fixed GUID.
+  pub fn with_endpoint(socket: Arc<UdpSocket>, endpoint: Endpoint) -> Listener {
+    let (shutdown, shutdown_receiver) = bounded(1);
+    let (stopped_sender, stopped) = bounded::<()>(1);
+    let (accepted, incoming) = unbounded();
+    let server_id = endpoint.guid();
+    let (bans, advertisement) = endpoint.shared();
+    let mut driver = Driver::new(socket, endpoint);
+    driver.listen(accepted, shutdown_receiver);
+    let status = Arc::new(Mutex::new(ListenerStatus::Running));
+    let driver_status = status.clone();
+    runtime::spawn(async move {
+      *driver_status.lock().unwrap() = match driver.run().await {
+        Ok(()) => ListenerStatus::Closed,
+        Err(e) => ListenerStatus::Failed(e.kind()),
+      };
+      drop(stopped_sender);
+    });
     Listener {
       shutdown,
-      recv_task,
+      stopped,
+      status,
+      incoming,
+      bans,
+      advertisement,
+      server_id,
     }
   }
-}
 
-struct ReceiverContext {
-  socket: Arc<UdpSocket>,
-  shutdown: Arc<AtomicBool>,
-}
+  //The GUID sent in offline replies, which Bedrock advertisements repeat.
+  pub fn server_id(&self) -> u64 {
+    self.server_id
+  }
 
-async fn receiver(config: SocketConfiguration, context: ReceiverContext) {
-  let mut buffer = Vec::with_capacity(config.recv_buffer_size);
-  unsafe { buffer.set_len(config.recv_buffer_size); }
-
-  loop {
-    let (size, remote) = match io::timeout(Duration::from_millis(500), context.socket.recv_from(&mut buffer)).await {
-      Ok(v) => v,
-      Err(e) if e.kind() == ErrorKind::TimedOut => {
-        if context.shutdown.load(Ordering::Relaxed) { break; }
-        else { continue; }
-      },
-      Err(e) => panic!("Error at receiver(9: {:?}", e),
-    };
-    handle(remote, &buffer[..size], &context);
+  //Sets the information string of the pong answering UnconnectedPing.
+  pub fn set_advertisement(&self, information: impl Into<String>) {
+    *self.advertisement.lock().unwrap() = Advertisement::Static(information.into());
+  }
+
+  //Builds the pong information for every ping instead, e.g. to report live player counts.
+  //The callback runs on the receiving task and should return quickly.
+  pub fn set_advertiser(&self, advertiser: impl Fn(&PingRequest) -> String + Send + 'static) {
+    *self.advertisement.lock().unwrap() = Advertisement::Dynamic(Box::new(advertiser));
+  }
+
+  pub fn status(&self) -> ListenerStatus {
+    *self.status.lock().unwrap()
+  }
+
+  //Waits for the next peer that completed the handshake. None once the listener is shut down
+  //or has failed, see `status`.
+  pub async fn accept(&self) -> Option<Connection> {
+    self.incoming.recv().await.ok()
+  }
+
+  pub fn incoming(&self) -> impl Stream<Item = Connection> {
+    self.incoming.clone()
+  }
+
+  //Refuses new handshakes from the network, for `duration` or until unbanned.
+  //Sessions that are already open are not affected.
+  pub fn ban(&self, network: IpNetwork, duration: Option<Duration>) {
+    self.bans.lock().unwrap().ban(network, duration, Instant::now());
   }
-}
 
-fn handle(address: SocketAddr, buffer: &[u8], context: &ReceiverContext) {
-  //
+  pub fn unban(&self, network: IpNetwork) -> bool {
+    self.bans.lock().unwrap().unban(network)
+  }
+
+  pub fn is_banned(&self, address: IpAddr) -> bool {
+    self.bans.lock().unwrap().is_banned(address, Instant::now())
+  }
+
+  //Stops accepting, sends DisconnectionNotification to every peer and returns once the
+  //receiving task has ended. Peers that don't acknowledge are dropped after the close timeout.
+  pub async fn shutdown(&self) {
+    self.shutdown.close();
+    let _ = self.stopped.recv().await;
+  }
 }
 
 impl Drop for Listener {
   fn drop(&mut self) {
-    self.shutdown.store(true, Ordering::Relaxed);
-    task::block_on(self.recv_task.take().unwrap());
+    //The receiving task still notifies the peers, but nothing waits for it.
+    if self.shutdown.close() {
+      warn!("listener dropped without shutdown, closing its sessions in the background");
+    }
   }
 }
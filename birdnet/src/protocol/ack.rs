@@ -0,0 +1,147 @@
+use crate::codable::{self, Codable, BytesCodingError, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BufMut};
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum AckRecord {
+  Single(u32),//u24
+  Range(u32, u32),//u24, u24
+}
+
+impl AckRecord {
+  pub fn contains(&self, sequence: u32) -> bool {
+    match *self {
+      AckRecord::Single(v) => v == sequence,
+      AckRecord::Range(min, max) => min <= sequence && sequence <= max,
+    }
+  }
+}
+
+#[derive(Default)]
+pub struct AckRecords(pub Vec<AckRecord>);
+
+impl AckRecords {
+  //`sequences` must be sorted in ascending order. Duplicates are merged.
+  pub fn from_sequences(sequences: &[u32]) -> AckRecords {
+    let mut records = Vec::new();
+    let mut iter = sequences.iter().copied();
+    let mut current = match iter.next() {
+      Some(first) => (first, first),
+      None => return AckRecords(records),
+    };
+    for sequence in iter {
+      if sequence == current.1 || sequence == current.1 + 1 {
+        current.1 = sequence;
+      }
+      else {
+        records.push(Self::record(current));
+        current = (sequence, sequence);
+      }
+    }
+    records.push(Self::record(current));
+    AckRecords(records)
+  }
+
+  fn record((min, max): (u32, u32)) -> AckRecord {
+    if min == max { AckRecord::Single(min) } else { AckRecord::Range(min, max) }
+  }
+
+  pub fn contains(&self, sequence: u32) -> bool {
+    self.0.iter().any(|record| record.contains(sequence))
+  }
+
+  pub fn sequences(&self) -> impl Iterator<Item = u32> + '_ {
+    self.0.iter().flat_map(|record| match *record {
+      AckRecord::Single(v) => v..=v,
+      AckRecord::Range(min, max) => min..=max,
+    })
+  }
+
+  pub fn into_inner(self) -> Vec<AckRecord> {
+    self.0
+  }
+}
+
+impl Codable for AckRecords {
+  fn encode(&self, mut buffer: &mut dyn BufMut) -> codable::Result<()> {
+    if self.0.len() > u16::MAX as usize {
+      return Err(BytesCodingError::InvalidInput("The maximum count(u16::MAX) of ack records is exceeded.".to_string()));
+    }
+    buffer.write_u16_be(self.0.len() as u16)?;
+    for record in &self.0 {
+      match *record {
+        AckRecord::Single(v) => {
+          buffer.write_u8(1)?;
+          buffer.write_u24_le(v)?;
+        },
+        AckRecord::Range(min, max) => {
+          buffer.write_u8(0)?;
+          buffer.write_u24_le(min)?;
+          buffer.write_u24_le(max)?;
+        },
+      }
+    }
+    Ok(())
+  }
+
+  fn decode(mut buffer: &mut dyn Buf) -> codable::Result<Self> {
+    let count = buffer.read_u16_be()? as usize;
+    let mut records = Vec::with_capacity(count);
+    for _ in 0..count {
+      if buffer.read_u8()? != 0 {
+        records.push(AckRecord::Single(buffer.read_u24_le()?));
+      }
+      else {
+        let min = buffer.read_u24_le()?;
+        let max = buffer.read_u24_le()?;
+        if min > max {
+          return Err(BytesCodingError::InvalidData("The range of ack record is reversed".to_string()));
+        }
+        records.push(AckRecord::Range(min, max));
+      }
+    }
+    Ok(AckRecords(records))
+  }
+}
+
+#[derive(Codable)]
+pub struct Ack {
+  pub id: u8,
+  pub records: AckRecords,
+}
+
+#[derive(Codable)]
+pub struct Nack {
+  pub id: u8,
+  pub records: AckRecords,
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn from_sequences_merges_runs_and_duplicates() {
+    let records = AckRecords::from_sequences(&[1, 2, 2, 3, 5, 7, 8]);
+    assert_eq!(records.0, [AckRecord::Range(1, 3), AckRecord::Single(5), AckRecord::Range(7, 8)]);
+    assert_eq!(records.sequences().collect::<Vec<_>>(), [1, 2, 3, 5, 7, 8]);
+    assert!(records.contains(2) && !records.contains(4));
+    assert!(AckRecords::from_sequences(&[]).0.is_empty());
+  }
+
+  #[test]
+  fn round_trip() {
+    let ack = Ack {
+      id: 0xc0,
+      records: AckRecords(vec![AckRecord::Single(0), AckRecord::Range(10, 0xffffff)]),
+    };
+    let bytes = ack.to_bytes().unwrap();
+    assert_eq!(bytes, [0xc0, 0, 2, 1, 0, 0, 0, 0, 10, 0, 0, 0xff, 0xff, 0xff]);
+    let decoded = Ack::from_bytes(&bytes).unwrap();
+    assert_eq!((decoded.id, decoded.records.0), (ack.id, ack.records.0));
+  }
+
+  #[test]
+  fn reversed_range_is_rejected() {
+    assert!(AckRecords::from_bytes(&[0, 1, 0, 5, 0, 0, 4, 0, 0]).is_err());
+  }
+}
@@ -1,5 +1,6 @@
 use crate::types::SystemAddress;
-use crate::constants::NUMBER_OF_INTERNAL_IDS;
+use crate::codable::{self, Codable, ReadBytesExt, WriteBytesExt};
+use bytes::{Buf, BufMut};
 
 #[derive(Codable)]
 pub struct ConnectionRequest {
@@ -16,21 +17,76 @@ pub struct ConnectionRequest {
   //}
 }
 
-#[derive(Codable)]
+//The number of internal addresses depends on the protocol version, see `version::internal_address_count`.
+//They are decoded up to the two trailing timestamps.
 pub struct ConnectionRequestAccepted {
   pub id: u8,
   pub client_address: SystemAddress,
   pub client_index: u16,
-  pub internal_addresses: [SystemAddress; NUMBER_OF_INTERNAL_IDS],
+  pub internal_addresses: Vec<SystemAddress>,
   pub ping_time: u64,
   pub pong_time: u64,
 }
 
-#[derive(Codable)]
+impl Codable for ConnectionRequestAccepted {
+  fn encode(&self, mut buffer: &mut dyn BufMut) -> codable::Result<()> {
+    buffer.write_u8(self.id)?;
+    self.client_address.encode(buffer)?;
+    buffer.write_u16_be(self.client_index)?;
+    for address in &self.internal_addresses {
+      address.encode(buffer)?;
+    }
+    buffer.write_u64_be(self.ping_time)?;
+    buffer.write_u64_be(self.pong_time)?;
+    Ok(())
+  }
+
+  fn decode(mut buffer: &mut dyn Buf) -> codable::Result<Self> {
+    let id = buffer.read_u8()?;
+    let client_address = SystemAddress::decode(buffer)?;
+    let client_index = buffer.read_u16_be()?;
+    let internal_addresses = decode_internal_addresses(buffer)?;
+    let ping_time = buffer.read_u64_be()?;
+    let pong_time = buffer.read_u64_be()?;
+    Ok(ConnectionRequestAccepted { id, client_address, client_index, internal_addresses, ping_time, pong_time })
+  }
+}
+
 pub struct NewIncomingConnection {
   pub id: u8,
   pub server_address: SystemAddress,
-  pub internal_addresses: [SystemAddress; NUMBER_OF_INTERNAL_IDS],
+  pub internal_addresses: Vec<SystemAddress>,
   pub ping_time: u64,
   pub pong_time: u64,
 }
+
+impl Codable for NewIncomingConnection {
+  fn encode(&self, mut buffer: &mut dyn BufMut) -> codable::Result<()> {
+    buffer.write_u8(self.id)?;
+    self.server_address.encode(buffer)?;
+    for address in &self.internal_addresses {
+      address.encode(buffer)?;
+    }
+    buffer.write_u64_be(self.ping_time)?;
+    buffer.write_u64_be(self.pong_time)?;
+    Ok(())
+  }
+
+  fn decode(mut buffer: &mut dyn Buf) -> codable::Result<Self> {
+    let id = buffer.read_u8()?;
+    let server_address = SystemAddress::decode(buffer)?;
+    let internal_addresses = decode_internal_addresses(buffer)?;
+    let ping_time = buffer.read_u64_be()?;
+    let pong_time = buffer.read_u64_be()?;
+    Ok(NewIncomingConnection { id, server_address, internal_addresses, ping_time, pong_time })
+  }
+}
+
+//ping_time(8) + pong_time(8)
+fn decode_internal_addresses(buffer: &mut dyn Buf) -> codable::Result<Vec<SystemAddress>> {
+  let mut addresses = Vec::new();
+  while buffer.remaining() > 16 {
+    addresses.push(SystemAddress::decode(buffer)?);
+  }
+  Ok(addresses)
+}
@@ -30,7 +30,7 @@ impl Codable for Datagram {
   }
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct InternalMessage {
   pub reliability: PacketReliability,
   pub splitted: bool,
@@ -44,11 +44,31 @@ pub struct InternalMessage {
   pub payload: Vec<u8>,
 }
 
+impl InternalMessage {
+  pub fn encoded_size(&self) -> usize {
+    let mut size = 3;//flags + length in bits
+    if self.reliability.is_reliable() {
+      size += 3;
+    }
+    if self.reliability.is_sequenced() {
+      size += 3;
+    }
+    if self.reliability.is_sequenced() || self.reliability.is_ordered() {
+      size += 4;
+    }
+    if self.splitted {
+      size += 10;
+    }
+    size + self.payload.len()
+  }
+}
+
 impl Codable for InternalMessage {
   fn encode(&self, mut buffer: &mut dyn BufMut) -> codable::Result<()> {
     buffer.write_u8((self.reliability.to_u8().unwrap() << 5) | ((self.splitted as u8) << 4))?;
-    assert!(self.payload.len() < u16::MAX.into());
-    buffer.write_u16_be(self.payload.len() as u16)?;
+    //The length is in bits, as in RakNet's dataBitLength.
+    assert!(self.payload.len() * 8 <= u16::MAX.into());
+    buffer.write_u16_be((self.payload.len() * 8) as u16)?;
     if self.reliability.is_reliable() {
       buffer.write_u24_le(self.message_index)?;
     }
@@ -71,8 +91,8 @@ impl Codable for InternalMessage {
   fn decode(mut buffer: &mut dyn Buf) -> codable::Result<Self> {
     let flgs =  buffer.read_u8()?;
     let reliability = PacketReliability::from_u8(flgs >> 5).unwrap();
-    let splitted = (flgs >> 4) == 1;
-    let length = buffer.read_u16_be()?;
+    let splitted = (flgs >> 4) & 1 == 1;
+    let length = buffer.read_u16_be()?.div_ceil(8);
     let mut message = InternalMessage {
       reliability,
       splitted,
@@ -93,8 +113,7 @@ impl Codable for InternalMessage {
       message.split_id = buffer.read_u16_be()?;
       message.split_index = buffer.read_u32_be()?;
     }
-    message.payload = Vec::with_capacity(length as usize);
-    unsafe { message.payload.set_len(length as usize); }
+    message.payload = vec![0u8; length as usize];
     buffer.read_exact(&mut message.payload)?;
 
     Ok(message)
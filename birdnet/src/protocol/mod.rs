@@ -1,4 +1,4 @@
-#[derive(FromPrimitive)]
+#[derive(FromPrimitive, Clone, Copy, PartialEq, Eq, Debug)]
 pub enum PacketIdentifiers {
   UnconnectedPing = 0x01,
   UnconnectedPingOpenConnection = 0x02,
@@ -33,3 +33,5 @@ pub mod open;
 pub mod disconnect;
 pub mod conn_request;
 pub mod datagram;
+pub mod ack;
+pub mod version;
@@ -1,5 +1,6 @@
 use crate::types::SystemAddress;
 use crate::codable::{self, Codable, ReadBytesExt, WriteBytesExt};
+use crate::protocol::version;
 use bytes::{Buf, BufMut};
 
 pub struct OpenConnectionRequest1 {
@@ -15,7 +16,7 @@ impl Codable for OpenConnectionRequest1 {
     buffer.write_u64_be(self.offline_magic[0])?;
     buffer.write_u64_be(self.offline_magic[1])?;
     buffer.write_u8(self.protocol)?;
-    let zeros = vec![0u8; (self.mtu_size - 28) as usize];
+    let zeros = vec![0u8; version::request1_padding(self.mtu_size)];
     buffer.write_all(&zeros)?;
     Ok(())
   }
@@ -29,7 +30,7 @@ impl Codable for OpenConnectionRequest1 {
     let protocol = buffer.read_u8()?;
     let remain = buffer.remaining();
     buffer.advance(remain);
-    let mtu_size = if remain > (u16::MAX as usize) - 28 { u16::MAX } else { 28u16 + remain as u16 };//
+    let mtu_size = version::request1_mtu_size(1 + 16 + 1 + remain);
     Ok(OpenConnectionRequest1 { id, offline_magic, protocol, mtu_size })
   }
 }
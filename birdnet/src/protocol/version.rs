@@ -0,0 +1,27 @@
+//Wire rules that depend on the RakNet protocol version, or that every version shares.
+use crate::constants::NUMBER_OF_INTERNAL_IDS;
+
+//Vanilla RakNet 4 is 6, Minecraft Bedrock uses 9, 10 and 11. Listed by preference.
+pub const SUPPORTED_PROTOCOL_VERSIONS: &[u8] = &[11, 10, 9, 6];
+
+//IPv4 + UDP headers, which the probed MTU includes.
+const UDP_HEADER_SIZE: usize = 28;
+
+//id(1) + offline magic(16) + protocol(1)
+const REQUEST1_HEADER_SIZE: usize = 18;
+
+//Vanilla RakNet sends 10 internal addresses in the connection handshake, the Bedrock versions 20.
+pub fn internal_address_count(protocol: u8) -> usize {
+  if protocol >= 9 { NUMBER_OF_INTERNAL_IDS } else { 10 }
+}
+
+//OpenConnectionRequest1 is zero padded so that the whole packet fills the probed MTU.
+//This is the same for every version.
+pub fn request1_padding(mtu_size: u16) -> usize {
+  (mtu_size as usize).saturating_sub(UDP_HEADER_SIZE + REQUEST1_HEADER_SIZE)
+}
+
+//The MTU a received OpenConnectionRequest1 of `length` bytes was probing.
+pub fn request1_mtu_size(length: usize) -> u16 {
+  (length + UDP_HEADER_SIZE).min(u16::MAX as usize) as u16
+}
@@ -0,0 +1,144 @@
+//The async runtime the listener, connector and discovery run on, picked with the
+//`runtime-async-std` (default) or `runtime-tokio` feature. Tokio wins if both are enabled.
+//With tokio, listeners and connectors must be created inside a tokio runtime.
+
+#[cfg(not(any(feature = "runtime-async-std", feature = "runtime-tokio")))]
+compile_error!("enable the `runtime-async-std` or the `runtime-tokio` feature");
+
+#[cfg(feature = "runtime-tokio")]
+mod imp {
+  use std::future::Future;
+  use std::time::Duration;
+
+  pub use tokio::net::UdpSocket;
+
+  #[cfg(target_os = "linux")]
+  pub use self::raw::RawSocket;
+
+  #[cfg(target_os = "linux")]
+  mod raw {
+    use super::UdpSocket;
+    use std::io;
+    use std::os::fd::{AsRawFd, RawFd};
+    use std::sync::Arc;
+    use tokio::io::Interest;
+
+    //Waits for readiness of a socket whose datagrams are read and written with raw syscalls.
+    pub struct RawSocket(Arc<UdpSocket>);
+
+    impl RawSocket {
+      pub fn new(socket: &Arc<UdpSocket>) -> io::Result<RawSocket> {
+        Ok(RawSocket(socket.clone()))
+      }
+
+      pub fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+      }
+
+      pub async fn read_with<R>(&self, op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+        self.ready(Interest::READABLE, op).await
+      }
+
+      pub async fn write_with<R>(&self, op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+        self.ready(Interest::WRITABLE, op).await
+      }
+
+      async fn ready<R>(&self, interest: Interest, mut op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+        let fd = self.0.as_raw_fd();
+        loop {
+          self.0.ready(interest).await?;
+          match self.0.try_io(interest, || op(fd)) {
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => continue,
+            result => return result,
+          }
+        }
+      }
+    }
+  }
+
+  pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    tokio::spawn(future);
+  }
+
+  pub fn from_std(socket: std::net::UdpSocket) -> std::io::Result<UdpSocket> {
+    UdpSocket::from_std(socket)
+  }
+
+  pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    tokio::time::timeout(duration, future).await.ok()
+  }
+
+  #[cfg(test)]
+  pub fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread().enable_all().build().unwrap().block_on(future)
+  }
+}
+
+#[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+mod imp {
+  use std::future::Future;
+  use std::time::Duration;
+
+  pub use async_std::net::UdpSocket;
+
+  #[cfg(target_os = "linux")]
+  pub use self::raw::RawSocket;
+
+  #[cfg(target_os = "linux")]
+  mod raw {
+    use super::UdpSocket;
+    use async_io::Async;
+    use std::io;
+    use std::os::fd::{AsRawFd, BorrowedFd, RawFd};
+    use std::sync::Arc;
+
+    //Waits for readiness of a socket whose datagrams are read and written with raw syscalls.
+    //async-std doesn't expose its reactor registration, so a duplicate of the socket is registered.
+    pub struct RawSocket(Async<std::net::UdpSocket>);
+
+    impl RawSocket {
+      pub fn new(socket: &Arc<UdpSocket>) -> io::Result<RawSocket> {
+        //The descriptor is valid while `socket` is borrowed.
+        let fd = unsafe { BorrowedFd::borrow_raw(socket.as_raw_fd()) }.try_clone_to_owned()?;
+        let socket = std::net::UdpSocket::from(fd);
+        Ok(RawSocket(Async::new(socket)?))
+      }
+
+      pub fn as_raw_fd(&self) -> RawFd {
+        self.0.as_raw_fd()
+      }
+
+      pub async fn read_with<R>(&self, mut op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+        self.0.read_with(|socket| op(socket.as_raw_fd())).await
+      }
+
+      pub async fn write_with<R>(&self, mut op: impl FnMut(RawFd) -> io::Result<R>) -> io::Result<R> {
+        self.0.write_with(|socket| op(socket.as_raw_fd())).await
+      }
+    }
+  }
+
+  pub fn spawn<F: Future<Output = ()> + Send + 'static>(future: F) {
+    async_std::task::spawn(future);
+  }
+
+  pub fn from_std(socket: std::net::UdpSocket) -> std::io::Result<UdpSocket> {
+    Ok(UdpSocket::from(socket))
+  }
+
+  pub async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    async_std::future::timeout(duration, future).await.ok()
+  }
+
+  #[cfg(test)]
+  pub fn block_on<F: Future>(future: F) -> F::Output {
+    async_std::task::block_on(future)
+  }
+}
+
+pub use imp::UdpSocket;
+#[cfg(target_os = "linux")]
+pub(crate) use imp::RawSocket;
+pub(crate) use imp::{spawn, timeout, from_std};
+#[cfg(test)]
+pub(crate) use imp::block_on;
@@ -0,0 +1,177 @@
+use std::time::{Duration, Instant};
+
+//Decides how many bytes may be unacknowledged at once.
+//The session calls it from the ACK/NACK flow and stops sending datagrams while the window is full.
+pub trait CongestionControl: Send {
+  fn window(&self) -> usize;
+  fn on_ack(&mut self, bytes: usize, rtt: Duration, now: Instant);
+  fn on_nack(&mut self, now: Instant);
+  fn on_timeout(&mut self, now: Instant);
+}
+
+#[derive(Clone, Copy, Default)]
+pub enum CongestionControlAlgorithm {
+  #[default]
+  SlidingWindow,
+  Cubic,
+  Custom(fn(mtu_size: u16) -> Box<dyn CongestionControl>),
+}
+
+impl CongestionControlAlgorithm {
+  pub fn build(self, mtu_size: u16) -> Box<dyn CongestionControl> {
+    match self {
+      CongestionControlAlgorithm::SlidingWindow => Box::new(SlidingWindow::new(mtu_size)),
+      CongestionControlAlgorithm::Cubic => Box::new(Cubic::new(mtu_size)),
+      CongestionControlAlgorithm::Custom(build) => build(mtu_size),
+    }
+  }
+}
+
+//RakNet's CCRakNetSlidingWindow: slow start until the threshold, then one MTU per window,
+//halving on loss at most once per round trip.
+pub struct SlidingWindow {
+  mtu_size: usize,
+  cwnd: usize,
+  ssthresh: Option<usize>,
+  rtt: Duration,
+  last_decrease: Option<Instant>,
+}
+
+impl SlidingWindow {
+  pub fn new(mtu_size: u16) -> SlidingWindow {
+    SlidingWindow {
+      mtu_size: mtu_size as usize,
+      cwnd: mtu_size as usize,
+      ssthresh: None,
+      rtt: Duration::ZERO,
+      last_decrease: None,
+    }
+  }
+
+  fn in_slow_start(&self) -> bool {
+    self.ssthresh.is_none_or(|ssthresh| self.cwnd < ssthresh)
+  }
+
+  fn can_decrease(&self, now: Instant) -> bool {
+    self.last_decrease.is_none_or(|last| now.saturating_duration_since(last) >= self.rtt)
+  }
+
+  fn decrease(&mut self, now: Instant) -> usize {
+    let ssthresh = (self.cwnd / 2).max(self.mtu_size * 2);
+    self.ssthresh = Some(ssthresh);
+    self.last_decrease = Some(now);
+    ssthresh
+  }
+}
+
+impl CongestionControl for SlidingWindow {
+  fn window(&self) -> usize {
+    self.cwnd
+  }
+
+  fn on_ack(&mut self, _bytes: usize, rtt: Duration, _now: Instant) {
+    self.rtt = rtt;
+    if self.in_slow_start() {
+      self.cwnd += self.mtu_size;
+    }
+    else {
+      self.cwnd += (self.mtu_size * self.mtu_size / self.cwnd).max(1);
+    }
+  }
+
+  fn on_nack(&mut self, now: Instant) {
+    if self.can_decrease(now) {
+      self.cwnd = self.decrease(now);
+    }
+  }
+
+  fn on_timeout(&mut self, now: Instant) {
+    if self.can_decrease(now) {
+      self.decrease(now);
+      self.cwnd = self.mtu_size;
+    }
+  }
+}
+
+const CUBIC_C: f64 = 0.4;
+const CUBIC_BETA: f64 = 0.7;
+
+//CUBIC (RFC 8312) counted in MTU-sized segments.
+pub struct Cubic {
+  mtu_size: usize,
+  cwnd: f64,
+  ssthresh: f64,
+  w_max: f64,
+  k: f64,
+  epoch_start: Option<Instant>,
+  min_rtt: Option<Duration>,
+  last_decrease: Option<Instant>,
+}
+
+impl Cubic {
+  pub fn new(mtu_size: u16) -> Cubic {
+    Cubic {
+      mtu_size: mtu_size as usize,
+      cwnd: 1.0,
+      ssthresh: f64::INFINITY,
+      w_max: 0.0,
+      k: 0.0,
+      epoch_start: None,
+      min_rtt: None,
+      last_decrease: None,
+    }
+  }
+
+  fn decrease(&mut self, now: Instant) -> bool {
+    let rtt = self.min_rtt.unwrap_or(Duration::ZERO);
+    if self.last_decrease.is_some_and(|last| now.saturating_duration_since(last) < rtt) {
+      return false;
+    }
+    self.last_decrease = Some(now);
+    self.epoch_start = None;
+    self.w_max = self.cwnd;
+    self.cwnd = (self.cwnd * CUBIC_BETA).max(2.0);
+    self.ssthresh = self.cwnd;
+    true
+  }
+}
+
+impl CongestionControl for Cubic {
+  fn window(&self) -> usize {
+    (self.cwnd * self.mtu_size as f64) as usize
+  }
+
+  fn on_ack(&mut self, _bytes: usize, rtt: Duration, now: Instant) {
+    let min_rtt = self.min_rtt.map_or(rtt, |min_rtt| min_rtt.min(rtt));
+    self.min_rtt = Some(min_rtt);
+    if self.cwnd < self.ssthresh {
+      self.cwnd += 1.0;
+      return;
+    }
+    let epoch_start = *self.epoch_start.get_or_insert_with(|| {
+      self.k = if self.w_max > self.cwnd { ((self.w_max - self.cwnd) / CUBIC_C).cbrt() } else { 0.0 };
+      now
+    });
+    if self.w_max < self.cwnd {
+      self.w_max = self.cwnd;
+    }
+    let t = (now.saturating_duration_since(epoch_start) + min_rtt).as_secs_f64();
+    let target = CUBIC_C * (t - self.k).powi(3) + self.w_max;
+    if target > self.cwnd {
+      self.cwnd += (target - self.cwnd) / self.cwnd;
+    }
+    else {
+      self.cwnd += 0.01 / self.cwnd;
+    }
+  }
+
+  fn on_nack(&mut self, now: Instant) {
+    self.decrease(now);
+  }
+
+  fn on_timeout(&mut self, now: Instant) {
+    if self.decrease(now) {
+      self.cwnd = 1.0;
+    }
+  }
+}
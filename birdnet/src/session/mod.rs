@@ -0,0 +1,743 @@
+use crate::codable::{self, Codable, BytesCodingError};
+use crate::constants::{PacketReliability, PacketPriority, OFFLINE_MAGIC};
+use crate::protocol::PacketIdentifiers;
+use crate::protocol::version;
+use crate::protocol::open::OpenConnectionReply2;
+use crate::protocol::conn_request::{ConnectionRequest, ConnectionRequestAccepted, NewIncomingConnection};
+use crate::protocol::datagram::{Datagram, InternalMessage};
+use crate::protocol::ack::{Ack, Nack, AckRecords};
+use crate::protocol::ping::{ConnectedPing, ConnectedPong};
+use crate::protocol::disconnect::DisconnectionNotification;
+use crate::connection::DisconnectReason;
+use crate::types::SystemAddress;
+
+use std::collections::VecDeque;
+use std::net::{SocketAddr, Ipv4Addr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use num_traits::FromPrimitive;
+use log::debug;
+
+pub mod reliability;
+pub mod split;
+pub mod ordering;
+pub mod scheduler;
+pub mod congestion;
+pub mod rtt;
+
+use reliability::{SendWindow, ReceiveWindow};
+use split::{Splitter, Assembler, MAX_MESSAGE_HEADER_SIZE};
+use ordering::{OrderingAssigner, OrderingChannels};
+use scheduler::Scheduler;
+use congestion::CongestionControl;
+use rtt::RttEstimator;
+use crate::socket::SocketConfiguration;
+
+//IPv4 + UDP headers.
+const UDP_HEADER_SIZE: usize = 28;
+
+//flags(1) + datagram sequence(3)
+const DATAGRAM_HEADER_SIZE: usize = 4;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Role {
+  Server,
+  Client,
+}
+
+//RequestReceived and RequestAccepted are only used by the server,
+//RequestSent only by the client. Closing drains the queues before notifying the peer.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum SessionState {
+  Unconnected,
+  OpenReplied,
+  RequestSent,
+  RequestReceived,
+  RequestAccepted,
+  Connected,
+  Closing,
+  Disconnecting,
+}
+
+#[derive(Debug)]
+pub enum SessionError {
+  UnexpectedPacket { state: SessionState, id: u8 },
+  NotConnected(SessionState),
+  InvalidChannel(u8),
+  //Would need more fragments than the split limit allows.
+  MessageTooLarge(usize),
+  //The peer sent an inconsistent split message, or more of them than the split limits allow.
+  SplitViolation,
+  //The peer made an ordering channel hold back more messages than allowed.
+  OrderingViolation,
+  Codec(BytesCodingError),
+}
+
+impl From<BytesCodingError> for SessionError {
+  fn from(e: BytesCodingError) -> Self {
+    SessionError::Codec(e)
+  }
+}
+
+pub type Result<T> = std::result::Result<T, SessionError>;
+
+pub enum SessionEvent {
+  Connected,
+  Message(Vec<u8>),
+  //The server refused the connection request with the packet of this id.
+  Rejected(u8),
+  Disconnected(DisconnectReason),
+}
+
+pub struct Session {
+  role: Role,
+  address: SocketAddr,
+  server_id: u64,
+  client_id: u64,
+  mtu_size: u16,
+  protocol: u8,
+  epoch: Instant,
+  state: SessionState,
+  send_window: SendWindow,
+  congestion: Box<dyn CongestionControl>,
+  rtt: RttEstimator,
+  receive_window: ReceiveWindow,
+  ordering_assigner: OrderingAssigner,
+  ordering_channels: OrderingChannels,
+  splitter: Splitter,
+  assembler: Assembler,
+  scheduler: Scheduler,
+  ordering_channel_count: usize,
+  max_split_count: u32,
+  outgoing: VecDeque<Vec<u8>>,
+  events: VecDeque<SessionEvent>,
+  ping_interval: Duration,
+  timeout: Duration,
+  //Both are set by the first update.
+  last_received: Option<Instant>,
+  last_ping: Option<Instant>,
+  close_timeout: Duration,
+  close_deadline: Option<Instant>,
+  notified: bool,
+  //Tells sessions with the same address apart over time, assigned by the endpoint.
+  id: u64,
+}
+
+impl Session {
+  //`protocol` is the version the peer asked for in OpenConnectionRequest1.
+  pub fn new(config: &SocketConfiguration, address: SocketAddr, server_id: u64, client_id: u64, mtu_size: u16, protocol: u8, epoch: Instant) -> Session {
+    let mut session = Self::with_role(config, Role::Server, address, server_id, client_id, mtu_size, epoch);
+    session.protocol = protocol;
+    session
+  }
+
+  //Creates the client side of a session once OpenConnectionReply2 has been received.
+  pub fn client(config: &SocketConfiguration, address: SocketAddr, server_id: u64, client_id: u64, mtu_size: u16, protocol: u8, epoch: Instant) -> Session {
+    let mut session = Self::with_role(config, Role::Client, address, server_id, client_id, mtu_size, epoch);
+    session.protocol = protocol;
+    session.state = SessionState::OpenReplied;
+    session
+  }
+
+  fn with_role(config: &SocketConfiguration, role: Role, address: SocketAddr, server_id: u64, client_id: u64, mtu_size: u16, epoch: Instant) -> Session {
+    Session {
+      congestion: config.congestion_control.build(mtu_size),
+      rtt: RttEstimator::new(config.min_rto, config.max_rto),
+      role,
+      address,
+      server_id,
+      client_id,
+      mtu_size,
+      protocol: version::SUPPORTED_PROTOCOL_VERSIONS[0],
+      epoch,
+      state: SessionState::Unconnected,
+      send_window: SendWindow::new(config.max_resends),
+      receive_window: ReceiveWindow::new(),
+      ordering_assigner: OrderingAssigner::new(config.ordering_channels),
+      ordering_channels: OrderingChannels::new(config.ordering_channels),
+      splitter: Splitter::new(),
+      assembler: Assembler::new(config.max_split_count, config.max_pending_splits),
+      scheduler: Scheduler::new(),
+      ordering_channel_count: config.ordering_channels,
+      max_split_count: config.max_split_count,
+      outgoing: VecDeque::new(),
+      events: VecDeque::new(),
+      ping_interval: config.ping_interval,
+      timeout: config.timeout,
+      last_received: None,
+      last_ping: None,
+      close_timeout: config.close_timeout,
+      close_deadline: None,
+      notified: false,
+      id: 0,
+    }
+  }
+
+  pub fn id(&self) -> u64 {
+    self.id
+  }
+
+  pub fn set_id(&mut self, id: u64) {
+    self.id = id;
+  }
+
+  pub fn role(&self) -> Role {
+    self.role
+  }
+
+  pub fn address(&self) -> SocketAddr {
+    self.address
+  }
+
+  pub fn server_id(&self) -> u64 {
+    self.server_id
+  }
+
+  pub fn client_id(&self) -> u64 {
+    self.client_id
+  }
+
+  pub fn mtu_size(&self) -> u16 {
+    self.mtu_size
+  }
+
+  pub fn protocol(&self) -> u8 {
+    self.protocol
+  }
+
+  pub fn state(&self) -> SessionState {
+    self.state
+  }
+
+  //Smoothed round-trip time, once the first ACK has arrived.
+  pub fn rtt(&self) -> Option<Duration> {
+    self.rtt.srtt()
+  }
+
+  pub fn rtt_estimator(&self) -> &RttEstimator {
+    &self.rtt
+  }
+
+  //Replaces the algorithm chosen by the configuration.
+  pub fn set_congestion_control(&mut self, congestion: Box<dyn CongestionControl>) {
+    self.congestion = congestion;
+  }
+
+  //A repeated OpenConnectionRequest2 means our reply was lost, so it is answered again.
+  pub fn open(&mut self) -> Result<OpenConnectionReply2> {
+    match self.state {
+      SessionState::Unconnected | SessionState::OpenReplied if self.role == Role::Server => {
+        self.state = SessionState::OpenReplied;
+        Ok(OpenConnectionReply2 {
+          id: PacketIdentifiers::OpenConnectionReply2 as u8,
+          offline_magic: OFFLINE_MAGIC,
+          server_id: self.server_id,
+          client_address: SystemAddress(self.address),
+          mtu_size: self.mtu_size,
+          security: false,
+        })
+      },
+      state => Err(SessionError::UnexpectedPacket { state, id: PacketIdentifiers::OpenConnectionRequest2 as u8 }),
+    }
+  }
+
+  pub fn connect(&mut self, now: Instant) -> Result<()> {
+    match self.state {
+      SessionState::OpenReplied if self.role == Role::Client => {
+        let request = ConnectionRequest {
+          id: PacketIdentifiers::ConnectionRequest as u8,
+          client_id: self.client_id,
+          ping_time: self.time(now),
+          security: false,
+        };
+        self.enqueue(request.to_bytes()?, PacketReliability::ReliableOrdered, PacketPriority::Immediate, 0);
+        self.state = SessionState::RequestSent;
+        Ok(())
+      },
+      state => Err(SessionError::UnexpectedPacket { state, id: PacketIdentifiers::ConnectionRequest as u8 }),
+    }
+  }
+
+  //Starts a graceful disconnect: messages queued so far are delivered, then a DisconnectionNotification,
+  //and the session is disconnected once that is acknowledged or the close timeout elapses.
+  pub fn close(&mut self, now: Instant) {
+    match self.state {
+      SessionState::Connected => {
+        self.state = SessionState::Closing;
+        self.close_deadline = Some(now + self.close_timeout);
+      },
+      SessionState::Closing | SessionState::Disconnecting => {},
+      _ => {
+        self.state = SessionState::Disconnecting;
+        self.events.push_back(SessionEvent::Disconnected(DisconnectReason::Closed));
+      },
+    }
+  }
+
+  //Handles a connected packet: a datagram, or an ACK/NACK which share its valid bit.
+  pub fn handle_packet(&mut self, buffer: &[u8], now: Instant) -> Result<()> {
+    self.last_received = Some(now);
+    match buffer.first() {
+      Some(flags) if flags & 0x40 != 0 => self.handle_ack(Ack::from_bytes(buffer)?, now),
+      Some(flags) if flags & 0x20 != 0 => self.handle_nack(Nack::from_bytes(buffer)?, now),
+      Some(_) => self.handle_datagram(Datagram::from_bytes(&buffer[1..])?, now)?,
+      None => {},
+    }
+    Ok(())
+  }
+
+  pub fn handle_datagram(&mut self, datagram: Datagram, now: Instant) -> Result<()> {
+    self.receive_window.receive_datagram(datagram.datagram_sequence);
+    for message in datagram.messages {
+      if message.reliability.is_reliable() && !self.receive_window.receive_message(message.message_index) {
+        continue;
+      }
+      let message = if message.splitted {
+        match self.assembler.insert(message) {
+          Ok(Some(message)) => message,
+          Ok(None) => continue,
+          Err(e) => {
+            self.abort(DisconnectReason::ProtocolViolation, now)?;
+            return Err(e);
+          },
+        }
+      } else { message };
+      if message.reliability.is_ordered() || message.reliability.is_sequenced() {
+        let delivered = match self.ordering_channels.insert(message) {
+          Ok(delivered) => delivered,
+          Err(e) => {
+            self.abort(DisconnectReason::ProtocolViolation, now)?;
+            return Err(e);
+          },
+        };
+        for message in delivered {
+          self.deliver(message.payload, now);
+        }
+      }
+      else {
+        self.deliver(message.payload, now);
+      }
+    }
+    Ok(())
+  }
+
+  //The message was acknowledged already, so a bad one must not take the rest of the datagram with it.
+  fn deliver(&mut self, payload: Vec<u8>, now: Instant) {
+    if let Err(e) = self.handle_message(payload, now) {
+      debug!("dropping a message from {}: {:?}", self.address, e);
+    }
+  }
+
+  pub fn handle_ack(&mut self, ack: Ack, now: Instant) {
+    for acknowledged in self.send_window.acknowledge(&ack.records) {
+      let rtt = now.saturating_duration_since(acknowledged.sent_at);
+      self.rtt.update(rtt);
+      self.congestion.on_ack(acknowledged.size, rtt, now);
+    }
+  }
+
+  pub fn handle_nack(&mut self, nack: Nack, now: Instant) {
+    if self.send_window.negative_acknowledge(&nack.records) > 0 {
+      self.congestion.on_nack(now);
+    }
+  }
+
+  //Flushes ACK/NACK records, retransmits lost messages and sends queued ones.
+  //Also keeps a connected peer alive with pings and drops a silent one.
+  pub fn update(&mut self, now: Instant) -> codable::Result<()> {
+    let last_received = *self.last_received.get_or_insert(now);
+    if self.state != SessionState::Disconnecting && now.saturating_duration_since(last_received) > self.timeout {
+      self.state = SessionState::Disconnecting;
+      self.events.push_back(SessionEvent::Disconnected(DisconnectReason::Timeout));
+      return Ok(());
+    }
+    let last_ping = *self.last_ping.get_or_insert(now);
+    if self.state == SessionState::Connected && now.saturating_duration_since(last_ping) >= self.ping_interval {
+      let ping = ConnectedPing {
+        id: PacketIdentifiers::ConnectedPing as u8,
+        ping_time: self.time(now),
+      };
+      self.enqueue(ping.to_bytes()?, PacketReliability::Unreliable, PacketPriority::Immediate, 0);
+      self.last_ping = Some(now);
+    }
+    self.send_acks()?;
+    if let Some(records) = self.receive_window.take_nacks() {
+      self.send_records(records, |records| Nack { id: PacketIdentifiers::Nack as u8, records })?;
+    }
+    if self.send_window.expire(now, self.rtt.rto()) > 0 {
+      self.rtt.on_timeout();
+      self.congestion.on_timeout(now);
+    }
+    if self.send_window.is_exhausted() {
+      self.state = SessionState::Disconnecting;
+      self.events.push_back(SessionEvent::Disconnected(DisconnectReason::Timeout));
+      return Ok(());
+    }
+    if self.state == SessionState::Closing {
+      self.drain(now)?;
+    }
+    self.flush(now)
+  }
+
+  //The notification is only queued once everything sent before it was acknowledged.
+  fn drain(&mut self, now: Instant) -> codable::Result<()> {
+    let drained = self.scheduler.is_empty() && self.send_window.is_empty();
+    if drained && !self.notified {
+      let notification = DisconnectionNotification {
+        id: PacketIdentifiers::DisconnectionNotification as u8,
+      };
+      self.enqueue(notification.to_bytes()?, PacketReliability::ReliableOrdered, PacketPriority::Immediate, 0);
+      self.notified = true;
+    }
+    else if drained {
+      self.state = SessionState::Disconnecting;
+      self.events.push_back(SessionEvent::Disconnected(DisconnectReason::Closed));
+    }
+    //The peer would otherwise only notice through its own timeout.
+    else if self.close_deadline.is_some_and(|deadline| now >= deadline) {
+      self.abort(DisconnectReason::Closed, now)?;
+    }
+    Ok(())
+  }
+
+  //Tears the session down right away, e.g. after a protocol violation, telling the peer without
+  //waiting for the congestion window or an acknowledgement.
+  fn abort(&mut self, reason: DisconnectReason, now: Instant) -> codable::Result<()> {
+    let notification = DisconnectionNotification {
+      id: PacketIdentifiers::DisconnectionNotification as u8,
+    };
+    let message = InternalMessage {
+      reliability: PacketReliability::Unreliable,
+      payload: notification.to_bytes()?,
+      ..Default::default()
+    };
+    self.send_datagram(vec![message], now)?;
+    self.state = SessionState::Disconnecting;
+    self.events.push_back(SessionEvent::Disconnected(reason));
+    Ok(())
+  }
+
+  fn send_acks(&mut self) -> codable::Result<()> {
+    if let Some(records) = self.receive_window.take_acks() {
+      self.send_records(records, |records| Ack { id: PacketIdentifiers::Ack as u8, records })?;
+    }
+    Ok(())
+  }
+
+  //Packs retransmissions first, then scheduled messages, into as few datagrams as the MTU allows.
+  //A datagram is only started while the congestion window has room, so it overshoots by at most one.
+  fn flush(&mut self, now: Instant) -> codable::Result<()> {
+    let capacity = (self.mtu_size as usize).saturating_sub(UDP_HEADER_SIZE + DATAGRAM_HEADER_SIZE);
+    let mut messages = Vec::new();
+    let mut size = 0;
+    while let Some(message_size) = self.send_window.peek_resend().or_else(|| self.scheduler.peek()).map(|message| message.encoded_size()) {
+      if !messages.is_empty() && size + message_size > capacity {
+        self.send_datagram(std::mem::take(&mut messages), now)?;
+        size = 0;
+      }
+      if messages.is_empty() && self.send_window.bytes_in_flight() >= self.congestion.window() {
+        break;
+      }
+      let message = match self.send_window.pop_resend() {
+        Some(message) => message,
+        None => self.scheduler.pop().unwrap(),
+      };
+      size += message_size;
+      messages.push(message);
+    }
+    if !messages.is_empty() {
+      self.send_datagram(messages, now)?;
+    }
+    Ok(())
+  }
+
+  //Queues an application payload. Immediate messages are sent without waiting for the next update.
+  pub fn send(&mut self, payload: Vec<u8>, reliability: PacketReliability, priority: PacketPriority, channel: u8, now: Instant) -> Result<()> {
+    if self.state != SessionState::Connected {
+      return Err(SessionError::NotConnected(self.state));
+    }
+    if channel as usize >= self.ordering_channel_count {
+      return Err(SessionError::InvalidChannel(channel));
+    }
+    if payload.len() > max_payload_size(self.mtu_size, self.max_split_count) {
+      return Err(SessionError::MessageTooLarge(payload.len()));
+    }
+    self.enqueue(payload, reliability, priority, channel);
+    if priority == PacketPriority::Immediate {
+      self.flush(now)?;
+    }
+    Ok(())
+  }
+
+  fn handle_message(&mut self, payload: Vec<u8>, now: Instant) -> Result<()> {
+    let id = match payload.first() {
+      Some(id) => *id,
+      None => return Ok(()),
+    };
+    match (PacketIdentifiers::from_u8(id), self.state) {
+      (Some(PacketIdentifiers::ConnectionRequest), SessionState::OpenReplied | SessionState::RequestAccepted) if self.role == Role::Server => {
+        let request = ConnectionRequest::from_bytes(&payload)?;
+        self.state = SessionState::RequestReceived;
+        let accepted = ConnectionRequestAccepted {
+          id: PacketIdentifiers::ConnectionRequestAccepted as u8,
+          client_address: SystemAddress(self.address),
+          client_index: 0,
+          internal_addresses: unspecified_addresses(self.protocol),
+          ping_time: request.ping_time,
+          pong_time: self.time(now),
+        };
+        self.enqueue(accepted.to_bytes()?, PacketReliability::ReliableOrdered, PacketPriority::Immediate, 0);
+        self.state = SessionState::RequestAccepted;
+      },
+      (Some(PacketIdentifiers::NewIncomingConnection), SessionState::RequestAccepted) => {
+        NewIncomingConnection::from_bytes(&payload)?;
+        self.state = SessionState::Connected;
+        self.events.push_back(SessionEvent::Connected);
+      },
+      (Some(PacketIdentifiers::ConnectionRequestAccepted), SessionState::RequestSent) => {
+        let accepted = ConnectionRequestAccepted::from_bytes(&payload)?;
+        let connection = NewIncomingConnection {
+          id: PacketIdentifiers::NewIncomingConnection as u8,
+          server_address: SystemAddress(self.address),
+          internal_addresses: unspecified_addresses(self.protocol),
+          ping_time: accepted.pong_time,
+          pong_time: self.time(now),
+        };
+        self.enqueue(connection.to_bytes()?, PacketReliability::ReliableOrdered, PacketPriority::Immediate, 0);
+        self.state = SessionState::Connected;
+        self.events.push_back(SessionEvent::Connected);
+      },
+      (Some(PacketIdentifiers::ConnectionBanned | PacketIdentifiers::NoFreeIncomingConnections | PacketIdentifiers::IpRecentryConnected), SessionState::RequestSent) => {
+        self.state = SessionState::Disconnecting;
+        self.events.push_back(SessionEvent::Rejected(id));
+      },
+      //Acknowledged right away, the session is torn down before the next update.
+      (Some(PacketIdentifiers::DisconnectionNotification), _) => {
+        self.send_acks()?;
+        self.state = SessionState::Disconnecting;
+        self.events.push_back(SessionEvent::Disconnected(DisconnectReason::Remote));
+      },
+      (Some(PacketIdentifiers::ConnectedPing), SessionState::RequestAccepted | SessionState::Connected) => {
+        let ping = ConnectedPing::from_bytes(&payload)?;
+        let pong = ConnectedPong {
+          id: PacketIdentifiers::ConnectedPong as u8,
+          ping_time: ping.ping_time,
+          pong_time: self.time(now),
+        };
+        self.enqueue(pong.to_bytes()?, PacketReliability::Unreliable, PacketPriority::Immediate, 0);
+      },
+      //RTT is measured from ACKs, the pong only proves the peer is alive.
+      (Some(PacketIdentifiers::ConnectedPong), SessionState::RequestAccepted | SessionState::Connected) => {
+        ConnectedPong::from_bytes(&payload)?;
+      },
+      //Already implied by a message that overtook it.
+      (Some(PacketIdentifiers::NewIncomingConnection), SessionState::Connected) if self.role == Role::Server => {},
+      (_, SessionState::Connected) => {
+        self.events.push_back(SessionEvent::Message(payload));
+      },
+      //NewIncomingConnection is ordered on channel 0, so the client's other messages can overtake it,
+      //or arrive without it if it was lost. They confirm the connection just as well.
+      (_, SessionState::RequestAccepted) if self.role == Role::Server => {
+        self.state = SessionState::Connected;
+        self.events.push_back(SessionEvent::Connected);
+        self.events.push_back(SessionEvent::Message(payload));
+      },
+      (_, state) => return Err(SessionError::UnexpectedPacket { state, id }),
+    }
+    Ok(())
+  }
+
+  fn enqueue(&mut self, payload: Vec<u8>, reliability: PacketReliability, priority: PacketPriority, channel: u8) {
+    let mut message = InternalMessage {
+      reliability,
+      order_channel: channel,
+      ..Default::default()
+    };
+    self.ordering_assigner.assign(&mut message);
+    let fragment_size = self.max_message_size();
+    if payload.len() > fragment_size {
+      for mut fragment in self.splitter.split(&message, &payload, fragment_size) {
+        fragment.message_index = self.send_window.next_message_index();
+        self.scheduler.push(fragment, priority);
+      }
+    }
+    else {
+      if reliability.is_reliable() {
+        message.message_index = self.send_window.next_message_index();
+      }
+      message.payload = payload;
+      self.scheduler.push(message, priority);
+    }
+  }
+
+  fn max_message_size(&self) -> usize {
+    max_message_size(self.mtu_size)
+  }
+
+  fn send_datagram(&mut self, messages: Vec<InternalMessage>, now: Instant) -> codable::Result<()> {
+    let datagram_sequence = self.send_window.next_datagram_sequence();
+    let mut datagram = Datagram {
+      datagram_sequence,
+      messages,
+    };
+    let mut buffer = vec![PacketIdentifiers::DatagramValid as u8];
+    datagram.encode(&mut buffer)?;
+    self.send_window.sent(datagram_sequence, &std::mem::take(&mut datagram.messages), buffer.len(), now);
+    self.outgoing.push_back(buffer);
+    Ok(())
+  }
+
+  //Splits the records so that every packet fits in the MTU.
+  fn send_records<T: Codable>(&mut self, records: AckRecords, packet: fn(AckRecords) -> T) -> codable::Result<()> {
+    //id(1) + count(2), and each record is at most flag(1) + min(3) + max(3).
+    let per_packet = (self.mtu_size as usize).saturating_sub(UDP_HEADER_SIZE + 3).max(7) / 7;
+    for chunk in records.0.chunks(per_packet) {
+      self.outgoing.push_back(packet(AckRecords(chunk.to_vec())).to_bytes()?);
+    }
+    Ok(())
+  }
+
+  pub fn poll_transmit(&mut self) -> Option<Vec<u8>> {
+    self.outgoing.pop_front()
+  }
+
+  pub fn poll_event(&mut self) -> Option<SessionEvent> {
+    self.events.pop_front()
+  }
+
+  fn time(&self, now: Instant) -> u64 {
+    now.saturating_duration_since(self.epoch).as_millis() as u64
+  }
+}
+
+pub(crate) fn u24_add(v: u32, n: u32) -> u32 {
+  v.wrapping_add(n) & 0xffffff
+}
+
+fn max_message_size(mtu_size: u16) -> usize {
+  (mtu_size as usize).saturating_sub(UDP_HEADER_SIZE + DATAGRAM_HEADER_SIZE + MAX_MESSAGE_HEADER_SIZE)
+}
+
+//The largest payload `send` accepts, split into at most `max_split_count` fragments.
+pub fn max_payload_size(mtu_size: u16, max_split_count: u32) -> usize {
+  max_message_size(mtu_size) * max_split_count as usize
+}
+
+pub(crate) fn u24_distance(from: u32, to: u32) -> u32 {
+  to.wrapping_sub(from) & 0xffffff
+}
+
+//Whether `a` precedes `b` in the wrapping u24 sequence space.
+pub(crate) fn u24_before(a: u32, b: u32) -> bool {
+  let distance = u24_distance(a, b);
+  distance != 0 && distance < 0x800000
+}
+
+fn unspecified_addresses(protocol: u8) -> Vec<SystemAddress> {
+  (0..version::internal_address_count(protocol))
+    .map(|_| SystemAddress(SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, 0))))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::iter;
+
+  fn deliver(from: &mut Session, to: &mut Session, now: Instant) {
+    while let Some(packet) = from.poll_transmit() {
+      let _ = to.handle_packet(&packet, now);
+    }
+  }
+
+  //Runs the connected handshake between a client and a server session.
+  fn connected(config: &SocketConfiguration, now: Instant) -> (Session, Session) {
+    let protocol = version::SUPPORTED_PROTOCOL_VERSIONS[0];
+    let mut server = Session::new(config, "10.0.0.2:50000".parse().unwrap(), 1, 2, 1400, protocol, now);
+    server.open().unwrap();
+    let mut client = Session::client(config, "10.0.0.1:19132".parse().unwrap(), 1, 2, 1400, protocol, now);
+    client.connect(now).unwrap();
+    for _ in 0..3 {
+      client.update(now).unwrap();
+      deliver(&mut client, &mut server, now);
+      server.update(now).unwrap();
+      deliver(&mut server, &mut client, now);
+    }
+    assert_eq!((client.state(), server.state()), (SessionState::Connected, SessionState::Connected));
+    while client.poll_event().is_some() {}
+    while server.poll_event().is_some() {}
+    (client, server)
+  }
+
+  fn reliable(message_index: u32, payload: Vec<u8>) -> InternalMessage {
+    InternalMessage {
+      reliability: PacketReliability::Reliable,
+      message_index,
+      payload,
+      ..Default::default()
+    }
+  }
+
+  fn messages(session: &mut Session) -> Vec<Vec<u8>> {
+    iter::from_fn(|| session.poll_event()).filter_map(|event| match event {
+      SessionEvent::Message(payload) => Some(payload),
+      _ => None,
+    }).collect()
+  }
+
+  #[test]
+  fn message_confirms_a_lost_new_incoming_connection() {
+    let now = Instant::now();
+    let protocol = version::SUPPORTED_PROTOCOL_VERSIONS[0];
+    let config = SocketConfiguration::default();
+    let mut server = Session::new(&config, "10.0.0.2:50000".parse().unwrap(), 1, 2, 1400, protocol, now);
+    server.open().unwrap();
+    let mut client = Session::client(&config, "10.0.0.1:19132".parse().unwrap(), 1, 2, 1400, protocol, now);
+    client.connect(now).unwrap();
+    client.update(now).unwrap();
+    deliver(&mut client, &mut server, now);
+    server.update(now).unwrap();
+    deliver(&mut server, &mut client, now);
+    assert_eq!(client.state(), SessionState::Connected);
+    //The datagram carrying NewIncomingConnection is lost.
+    client.update(now).unwrap();
+    while client.poll_transmit().is_some() {}
+    client.send(vec![0x90, 1], PacketReliability::Reliable, PacketPriority::Medium, 1, now).unwrap();
+    client.update(now).unwrap();
+    deliver(&mut client, &mut server, now);
+    assert!(matches!(server.poll_event(), Some(SessionEvent::Connected)));
+    assert_eq!(messages(&mut server), [vec![0x90, 1]]);
+    assert_eq!(server.state(), SessionState::Connected);
+  }
+
+  #[test]
+  fn close_notifies_the_peer_after_the_deadline() {
+    let start = Instant::now();
+    let config = SocketConfiguration::builder().close_timeout(Duration::from_millis(500)).build().unwrap();
+    let (mut client, mut server) = connected(&config, start);
+    client.send(vec![0x90; 10], PacketReliability::Reliable, PacketPriority::Medium, 0, start).unwrap();
+    client.close(start);
+    //Nothing reaches the server until the deadline has passed.
+    let mut now = start;
+    while client.state() != SessionState::Disconnecting {
+      assert!(now < start + Duration::from_secs(1));
+      while client.poll_transmit().is_some() {}
+      now += Duration::from_millis(10);
+      client.update(now).unwrap();
+    }
+    assert!(matches!(client.poll_event(), Some(SessionEvent::Disconnected(DisconnectReason::Closed))));
+    deliver(&mut client, &mut server, now);
+    assert!(matches!(server.poll_event(), Some(SessionEvent::Disconnected(DisconnectReason::Remote))));
+  }
+
+  #[test]
+  fn bad_message_keeps_the_rest_of_the_datagram() {
+    let now = Instant::now();
+    let (_, mut server) = connected(&Default::default(), now);
+    let datagram = Datagram {
+      datagram_sequence: 100,
+      messages: vec![reliable(100, vec![PacketIdentifiers::ConnectedPing as u8]), reliable(101, vec![0x90, 7])],
+    };
+    server.handle_datagram(datagram, now).unwrap();
+    assert_eq!(messages(&mut server), [vec![0x90, 7]]);
+  }
+}
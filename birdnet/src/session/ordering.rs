@@ -0,0 +1,205 @@
+use super::{u24_add, u24_before, Result, SessionError};
+use crate::protocol::datagram::InternalMessage;
+
+use std::collections::HashMap;
+
+//Messages a channel holds back while waiting for an earlier ordered one. A peer exceeding it is
+//either malicious, e.g. sending unreliable sequenced messages for a future order index, or
+//far beyond what any reliable window would leave outstanding.
+const MAX_HELD_MESSAGES: usize = 1024;
+
+//Sequenced messages carry the order index the next ordered message will get,
+//so they are delivered after every ordered message sent before them.
+pub struct OrderingAssigner {
+  order_index: Vec<u32>,
+  sequence: Vec<u32>,
+}
+
+impl OrderingAssigner {
+  pub fn new(channels: usize) -> OrderingAssigner {
+    OrderingAssigner {
+      order_index: vec![0; channels],
+      sequence: vec![0; channels],
+    }
+  }
+
+  pub fn assign(&mut self, message: &mut InternalMessage) {
+    let channel = message.order_channel as usize % self.order_index.len();
+    if message.reliability.is_ordered() {
+      message.order_index = self.order_index[channel];
+      self.order_index[channel] = u24_add(self.order_index[channel], 1);
+      self.sequence[channel] = 0;
+    }
+    else if message.reliability.is_sequenced() {
+      message.order_index = self.order_index[channel];
+      message.sequence = self.sequence[channel];
+      self.sequence[channel] = u24_add(self.sequence[channel], 1);
+    }
+  }
+}
+
+#[derive(Default)]
+struct Held {
+  sequenced: Vec<InternalMessage>,
+  ordered: Option<InternalMessage>,
+}
+
+#[derive(Default)]
+struct Channel {
+  expected: u32,
+  highest_sequence: Option<u32>,
+  held: HashMap<u32, Held>,
+  held_count: usize,
+}
+
+impl Channel {
+  fn deliver_sequenced(&mut self, message: InternalMessage, delivered: &mut Vec<InternalMessage>) {
+    let newer = match self.highest_sequence {
+      Some(highest) => u24_before(highest, message.sequence),
+      None => true,
+    };
+    if newer {
+      self.highest_sequence = Some(message.sequence);
+      delivered.push(message);
+    }
+  }
+
+  fn deliver_ordered(&mut self, message: InternalMessage, delivered: &mut Vec<InternalMessage>) {
+    self.expected = u24_add(self.expected, 1);
+    self.highest_sequence = None;
+    delivered.push(message);
+  }
+
+  fn drain(&mut self, delivered: &mut Vec<InternalMessage>) {
+    while let Some(held) = self.held.remove(&self.expected) {
+      self.held_count -= held.sequenced.len() + held.ordered.is_some() as usize;
+      let mut sequenced = held.sequenced;
+      sequenced.sort_by_key(|message| message.sequence);
+      for message in sequenced {
+        self.deliver_sequenced(message, delivered);
+      }
+      match held.ordered {
+        Some(message) => self.deliver_ordered(message, delivered),
+        None => break,
+      }
+    }
+  }
+}
+
+pub struct OrderingChannels {
+  channels: Vec<Channel>,
+}
+
+impl OrderingChannels {
+  //Messages on channels beyond `channels` are dropped.
+  pub fn new(channels: usize) -> OrderingChannels {
+    OrderingChannels {
+      channels: (0..channels).map(|_| Default::default()).collect(),
+    }
+  }
+
+  //Returns the messages that became deliverable, in delivery order.
+  //Messages older than what the channel already delivered are dropped.
+  //Fails once the channel would hold back more than MAX_HELD_MESSAGES.
+  pub fn insert(&mut self, message: InternalMessage) -> Result<Vec<InternalMessage>> {
+    let mut delivered = Vec::new();
+    let channel = match self.channels.get_mut(message.order_channel as usize) {
+      Some(channel) => channel,
+      None => return Ok(delivered),
+    };
+    if u24_before(message.order_index, channel.expected) {
+      return Ok(delivered);
+    }
+    if message.order_index != channel.expected {
+      if channel.held_count >= MAX_HELD_MESSAGES {
+        return Err(SessionError::OrderingViolation);
+      }
+      let held = channel.held.entry(message.order_index).or_default();
+      if message.reliability.is_ordered() {
+        channel.held_count += held.ordered.is_none() as usize;
+        held.ordered = Some(message);
+      }
+      else {
+        channel.held_count += 1;
+        held.sequenced.push(message);
+      }
+      return Ok(delivered);
+    }
+    if message.reliability.is_ordered() {
+      channel.deliver_ordered(message, &mut delivered);
+      channel.drain(&mut delivered);
+    }
+    else {
+      channel.deliver_sequenced(message, &mut delivered);
+    }
+    Ok(delivered)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::constants::PacketReliability;
+
+  //Assigns ordering fields as the sender would, with the payload naming the message.
+  fn messages(kinds: &[PacketReliability]) -> Vec<InternalMessage> {
+    let mut assigner = OrderingAssigner::new(1);
+    kinds.iter().enumerate().map(|(index, reliability)| {
+      let mut message = InternalMessage {
+        reliability: *reliability,
+        payload: vec![index as u8],
+        ..Default::default()
+      };
+      assigner.assign(&mut message);
+      message
+    }).collect()
+  }
+
+  fn payloads(delivered: Vec<InternalMessage>) -> Vec<u8> {
+    delivered.into_iter().map(|message| message.payload[0]).collect()
+  }
+
+  #[test]
+  fn ordered_messages_are_held_back() {
+    let sent = messages(&[PacketReliability::ReliableOrdered; 4]);
+    let mut channels = OrderingChannels::new(1);
+    assert!(channels.insert(sent[2].clone()).unwrap().is_empty());
+    assert!(channels.insert(sent[1].clone()).unwrap().is_empty());
+    assert_eq!(payloads(channels.insert(sent[0].clone()).unwrap()), [0, 1, 2]);
+    assert!(channels.insert(sent[1].clone()).unwrap().is_empty());
+    assert_eq!(payloads(channels.insert(sent[3].clone()).unwrap()), [3]);
+  }
+
+  #[test]
+  fn stale_sequenced_messages_are_dropped() {
+    use PacketReliability::{ReliableOrdered, UnreliableSequenced};
+    let sent = messages(&[UnreliableSequenced, UnreliableSequenced, ReliableOrdered, UnreliableSequenced, UnreliableSequenced]);
+    let mut channels = OrderingChannels::new(1);
+    assert_eq!(payloads(channels.insert(sent[1].clone()).unwrap()), [1]);
+    assert!(channels.insert(sent[0].clone()).unwrap().is_empty());
+    //Sequenced after the ordered message, so held until it arrives.
+    assert!(channels.insert(sent[4].clone()).unwrap().is_empty());
+    assert_eq!(payloads(channels.insert(sent[2].clone()).unwrap()), [2, 4]);
+    assert!(channels.insert(sent[3].clone()).unwrap().is_empty());
+  }
+
+  #[test]
+  fn holding_back_is_bounded() {
+    let ordered = messages(&[PacketReliability::ReliableOrdered]).remove(0);
+    let mut channels = OrderingChannels::new(1);
+    let future = InternalMessage { reliability: PacketReliability::UnreliableSequenced, order_index: 1, ..ordered.clone() };
+    for sequence in 0..MAX_HELD_MESSAGES as u32 {
+      assert!(channels.insert(InternalMessage { sequence, ..future.clone() }).unwrap().is_empty());
+    }
+    assert!(matches!(channels.insert(future), Err(SessionError::OrderingViolation)));
+    assert_eq!(channels.insert(ordered).unwrap().len(), 1 + MAX_HELD_MESSAGES);
+    assert_eq!(channels.channels[0].held_count, 0);
+  }
+
+  #[test]
+  fn unknown_channels_are_dropped() {
+    let mut message = messages(&[PacketReliability::ReliableOrdered]).remove(0);
+    message.order_channel = 1;
+    assert!(OrderingChannels::new(1).insert(message).unwrap().is_empty());
+  }
+}
@@ -0,0 +1,372 @@
+use super::{u24_add, u24_distance, u24_before};
+use crate::protocol::ack::{AckRecord, AckRecords};
+use crate::protocol::datagram::InternalMessage;
+
+use std::collections::{BTreeMap, HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+//Gaps wider than this are not NACKed; the sender's RTO covers them.
+const MAX_NACK_GAP: u32 = 1024;
+
+//How far past the oldest missing message index a message may be. A sender would need this many
+//unacknowledged reliable messages to exceed it, so anything further is dropped, which also bounds
+//the indices held for deduplication.
+const MESSAGE_WINDOW_SIZE: u32 = 1 << 16;
+
+struct InFlight {
+  messages: Vec<InternalMessage>,
+  size: usize,
+  sent_at: Instant,
+}
+
+pub struct Acknowledged {
+  pub size: usize,
+  pub sent_at: Instant,
+}
+
+pub struct SendWindow {
+  datagram_sequence: u32,
+  message_index: u32,
+  in_flight: BTreeMap<u32, InFlight>,
+  bytes_in_flight: usize,
+  resend: VecDeque<InternalMessage>,
+  max_resends: u32,
+  //Retransmissions so far, by message index.
+  resends: HashMap<u32, u32>,
+  exhausted: bool,
+}
+
+impl SendWindow {
+  pub fn new(max_resends: u32) -> SendWindow {
+    SendWindow {
+      datagram_sequence: 0,
+      message_index: 0,
+      in_flight: BTreeMap::new(),
+      bytes_in_flight: 0,
+      resend: VecDeque::new(),
+      max_resends,
+      resends: HashMap::new(),
+      exhausted: false,
+    }
+  }
+
+  pub fn next_message_index(&mut self) -> u32 {
+    let index = self.message_index;
+    self.message_index = u24_add(index, 1);
+    index
+  }
+
+  pub fn next_datagram_sequence(&mut self) -> u32 {
+    let sequence = self.datagram_sequence;
+    self.datagram_sequence = u24_add(sequence, 1);
+    sequence
+  }
+
+  //Tracks every sent datagram until it is acknowledged, keeping only its reliable messages for resending.
+  pub fn sent(&mut self, sequence: u32, messages: &[InternalMessage], size: usize, now: Instant) {
+    let messages: Vec<InternalMessage> = messages.iter()
+      .filter(|message| message.reliability.is_reliable())
+      .cloned()
+      .collect();
+    self.bytes_in_flight += size;
+    if let Some(replaced) = self.in_flight.insert(sequence, InFlight { messages, size, sent_at: now }) {
+      self.bytes_in_flight -= replaced.size;
+    }
+  }
+
+  pub fn acknowledge(&mut self, records: &AckRecords) -> Vec<Acknowledged> {
+    let mut acknowledged = Vec::new();
+    for sequence in self.matching(records) {
+      if let Some(in_flight) = self.remove(sequence) {
+        for message in &in_flight.messages {
+          self.resends.remove(&message.message_index);
+        }
+        acknowledged.push(Acknowledged { size: in_flight.size, sent_at: in_flight.sent_at });
+      }
+    }
+    acknowledged
+  }
+
+  //Returns how many datagrams were lost.
+  pub fn negative_acknowledge(&mut self, records: &AckRecords) -> usize {
+    let mut lost = 0;
+    for sequence in self.matching(records) {
+      if let Some(in_flight) = self.remove(sequence) {
+        self.retransmit(in_flight.messages);
+        lost += 1;
+      }
+    }
+    lost
+  }
+
+  //Returns how many datagrams were lost.
+  pub fn expire(&mut self, now: Instant, rto: Duration) -> usize {
+    let expired: Vec<u32> = self.in_flight.iter()
+      .filter(|(_, in_flight)| now.saturating_duration_since(in_flight.sent_at) >= rto)
+      .map(|(sequence, _)| *sequence)
+      .collect();
+    let lost = expired.len();
+    for sequence in expired {
+      if let Some(in_flight) = self.remove(sequence) {
+        self.retransmit(in_flight.messages);
+      }
+    }
+    lost
+  }
+
+  fn retransmit(&mut self, messages: Vec<InternalMessage>) {
+    for message in messages {
+      let resends = self.resends.entry(message.message_index).or_insert(0);
+      *resends += 1;
+      self.exhausted |= *resends > self.max_resends;
+      self.resend.push_back(message);
+    }
+  }
+
+  //Whether a message was retransmitted more often than allowed.
+  pub fn is_exhausted(&self) -> bool {
+    self.exhausted
+  }
+
+  fn remove(&mut self, sequence: u32) -> Option<InFlight> {
+    let in_flight = self.in_flight.remove(&sequence)?;
+    self.bytes_in_flight -= in_flight.size;
+    Some(in_flight)
+  }
+
+  pub fn bytes_in_flight(&self) -> usize {
+    self.bytes_in_flight
+  }
+
+  pub fn pop_resend(&mut self) -> Option<InternalMessage> {
+    self.resend.pop_front()
+  }
+
+  pub fn peek_resend(&self) -> Option<&InternalMessage> {
+    self.resend.front()
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.in_flight.is_empty() && self.resend.is_empty()
+  }
+
+  fn matching(&self, records: &AckRecords) -> Vec<u32> {
+    let mut sequences = Vec::new();
+    for record in &records.0 {
+      let (min, max) = match *record {
+        AckRecord::Single(v) => (v, v),
+        AckRecord::Range(min, max) => (min, max),
+      };
+      sequences.extend(self.in_flight.range(min..=max).map(|(sequence, _)| *sequence));
+    }
+    sequences
+  }
+}
+
+#[derive(Default)]
+pub struct ReceiveWindow {
+  expected_sequence: u32,
+  acks: Vec<u32>,
+  nacks: Vec<u32>,
+  message_base: u32,
+  received: HashSet<u32>,
+}
+
+impl ReceiveWindow {
+  pub fn new() -> ReceiveWindow {
+    Default::default()
+  }
+
+  pub fn receive_datagram(&mut self, sequence: u32) {
+    self.acks.push(sequence);
+    if sequence == self.expected_sequence {
+      self.expected_sequence = u24_add(sequence, 1);
+    }
+    else if u24_before(self.expected_sequence, sequence) {
+      let gap = u24_distance(self.expected_sequence, sequence);
+      if gap <= MAX_NACK_GAP {
+        self.nacks.extend((0..gap).map(|i| u24_add(self.expected_sequence, i)));
+      }
+      self.expected_sequence = u24_add(sequence, 1);
+    }
+    else {
+      self.nacks.retain(|v| *v != sequence);
+    }
+  }
+
+  //Returns false if the message index was already received or is outside the window.
+  pub fn receive_message(&mut self, index: u32) -> bool {
+    if index != self.message_base && !u24_before(self.message_base, index) {
+      return false;
+    }
+    if u24_distance(self.message_base, index) >= MESSAGE_WINDOW_SIZE {
+      return false;
+    }
+    if !self.received.insert(index) {
+      return false;
+    }
+    while self.received.remove(&self.message_base) {
+      self.message_base = u24_add(self.message_base, 1);
+    }
+    true
+  }
+
+  pub fn take_acks(&mut self) -> Option<AckRecords> {
+    Self::take_records(&mut self.acks)
+  }
+
+  pub fn take_nacks(&mut self) -> Option<AckRecords> {
+    Self::take_records(&mut self.nacks)
+  }
+
+  fn take_records(sequences: &mut Vec<u32>) -> Option<AckRecords> {
+    if sequences.is_empty() {
+      return None;
+    }
+    sequences.sort_unstable();
+    let records = AckRecords::from_sequences(sequences);
+    sequences.clear();
+    Some(records)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::constants::PacketReliability;
+
+  fn message(reliability: PacketReliability, message_index: u32) -> InternalMessage {
+    InternalMessage {
+      reliability,
+      message_index,
+      ..Default::default()
+    }
+  }
+
+  fn records(records: &[AckRecord]) -> AckRecords {
+    AckRecords(records.to_vec())
+  }
+
+  fn resent(window: &mut SendWindow) -> Vec<u32> {
+    std::iter::from_fn(|| window.pop_resend()).map(|message| message.message_index).collect()
+  }
+
+  #[test]
+  fn acks_and_nacks_match_in_flight_datagrams() {
+    let now = Instant::now();
+    let mut window = SendWindow::new(10);
+    for index in 0..5 {
+      let sequence = window.next_datagram_sequence();
+      window.sent(sequence, &[message(PacketReliability::Reliable, index)], 100, now);
+    }
+    let sequence = window.next_datagram_sequence();
+    window.sent(sequence, &[message(PacketReliability::Unreliable, 0)], 50, now);
+    assert_eq!(window.bytes_in_flight(), 550);
+
+    let acknowledged = window.acknowledge(&records(&[AckRecord::Range(0, 1), AckRecord::Single(4), AckRecord::Single(9)]));
+    assert_eq!(acknowledged.len(), 3);
+    assert_eq!(window.bytes_in_flight(), 250);
+    //Already acknowledged datagrams are not lost.
+    assert_eq!(window.negative_acknowledge(&records(&[AckRecord::Range(0, 2), AckRecord::Single(5)])), 2);
+    assert_eq!(window.bytes_in_flight(), 100);
+    //Only reliable messages are resent.
+    assert_eq!(resent(&mut window), [2]);
+    assert_eq!(window.acknowledge(&records(&[AckRecord::Single(3)])).len(), 1);
+    assert!(window.is_empty());
+  }
+
+  #[test]
+  fn unacknowledged_datagrams_expire() {
+    let now = Instant::now();
+    let rto = Duration::from_millis(100);
+    let mut window = SendWindow::new(10);
+    window.sent(0, &[message(PacketReliability::Reliable, 0)], 100, now);
+    window.sent(1, &[message(PacketReliability::Reliable, 1)], 100, now + Duration::from_millis(50));
+    assert_eq!(window.expire(now + Duration::from_millis(99), rto), 0);
+    assert_eq!(window.expire(now + rto, rto), 1);
+    assert_eq!(resent(&mut window), [0]);
+    assert_eq!(window.bytes_in_flight(), 100);
+  }
+
+  #[test]
+  fn resends_are_limited() {
+    let mut now = Instant::now();
+    let rto = Duration::from_millis(100);
+    let mut window = SendWindow::new(2);
+    let mut resend = vec![message(PacketReliability::Reliable, 7)];
+    for resends in 1..=3 {
+      let sequence = window.next_datagram_sequence();
+      window.sent(sequence, &resend, 100, now);
+      now += rto;
+      assert_eq!(window.expire(now, rto), 1);
+      assert_eq!(window.is_exhausted(), resends > 2);
+      resend = std::iter::from_fn(|| window.pop_resend()).collect();
+    }
+
+    //An acknowledgement resets the count.
+    let mut window = SendWindow::new(1);
+    window.sent(0, &[message(PacketReliability::Reliable, 7)], 100, now);
+    window.negative_acknowledge(&records(&[AckRecord::Single(0)]));
+    let resend: Vec<_> = std::iter::from_fn(|| window.pop_resend()).collect();
+    window.sent(1, &resend, 100, now);
+    window.acknowledge(&records(&[AckRecord::Single(1)]));
+    window.sent(2, &resend, 100, now);
+    window.negative_acknowledge(&records(&[AckRecord::Single(2)]));
+    assert!(!window.is_exhausted());
+  }
+
+  #[test]
+  fn duplicate_messages_are_rejected() {
+    let mut window = ReceiveWindow::new();
+    assert!(window.receive_message(0));
+    assert!(!window.receive_message(0));
+    assert!(window.receive_message(2));
+    assert!(!window.receive_message(2));
+    assert!(window.receive_message(1));
+    assert!(!window.receive_message(1));
+    assert_eq!(window.message_base, 3);
+    assert!(window.received.is_empty());
+  }
+
+  #[test]
+  fn message_indices_wrap() {
+    let mut window = ReceiveWindow { message_base: 0xFFFFFE, ..Default::default() };
+    assert!(window.receive_message(0));
+    assert!(window.receive_message(0xFFFFFF));
+    assert!(window.receive_message(0xFFFFFE));
+    assert!(!window.receive_message(0xFFFFFF));
+    assert!(!window.receive_message(0));
+    assert!(window.receive_message(1));
+    assert_eq!(window.message_base, 2);
+  }
+
+  #[test]
+  fn messages_outside_the_window_are_dropped() {
+    let mut window = ReceiveWindow::new();
+    assert!(!window.receive_message(MESSAGE_WINDOW_SIZE));
+    assert!(!window.receive_message(0xFFFFFF));
+    assert!(window.receive_message(MESSAGE_WINDOW_SIZE - 1));
+    assert!(window.receive_message(0));
+    assert!(window.receive_message(MESSAGE_WINDOW_SIZE));
+  }
+
+  #[test]
+  fn gaps_are_nacked() {
+    let mut window = ReceiveWindow::new();
+    window.receive_datagram(0);
+    window.receive_datagram(4);
+    //A late datagram is no longer missing.
+    window.receive_datagram(2);
+    assert_eq!(window.take_acks().unwrap().0, [AckRecord::Single(0), AckRecord::Single(2), AckRecord::Single(4)]);
+    assert_eq!(window.take_nacks().unwrap().0, [AckRecord::Single(1), AckRecord::Single(3)]);
+    assert!(window.take_nacks().is_none());
+
+    //Too wide a gap is left to the sender's RTO.
+    window.receive_datagram(5 + MAX_NACK_GAP + 1);
+    assert!(window.take_nacks().is_none());
+
+    let mut window = ReceiveWindow { expected_sequence: 0xFFFFFE, ..Default::default() };
+    window.receive_datagram(1);
+    assert_eq!(window.take_nacks().unwrap().0, [AckRecord::Single(0), AckRecord::Range(0xFFFFFE, 0xFFFFFF)]);
+  }
+}
@@ -0,0 +1,64 @@
+use std::time::Duration;
+
+//Covers the delay of ACKs, which are flushed once per update.
+const GRANULARITY: Duration = Duration::from_millis(10);
+
+const INITIAL_RTO: Duration = Duration::from_secs(1);
+
+//Jacobson/Karels estimation as in RFC 6298. Retransmitted messages go out in new datagrams,
+//so every ACK matches exactly one transmission and every sample is unambiguous.
+pub struct RttEstimator {
+  srtt: Option<Duration>,
+  rttvar: Duration,
+  backoff: u32,
+  min_rto: Duration,
+  max_rto: Duration,
+}
+
+impl RttEstimator {
+  pub fn new(min_rto: Duration, max_rto: Duration) -> RttEstimator {
+    RttEstimator {
+      srtt: None,
+      rttvar: Duration::ZERO,
+      backoff: 0,
+      min_rto,
+      max_rto,
+    }
+  }
+
+  pub fn update(&mut self, sample: Duration) {
+    match self.srtt {
+      None => {
+        self.srtt = Some(sample);
+        self.rttvar = sample / 2;
+      },
+      Some(srtt) => {
+        let delta = srtt.abs_diff(sample);
+        self.rttvar = (self.rttvar * 3 + delta) / 4;
+        self.srtt = Some((srtt * 7 + sample) / 8);
+      },
+    }
+    self.backoff = 0;
+  }
+
+  //Doubles the RTO until the next sample arrives.
+  pub fn on_timeout(&mut self) {
+    self.backoff = (self.backoff + 1).min(16);
+  }
+
+  pub fn srtt(&self) -> Option<Duration> {
+    self.srtt
+  }
+
+  pub fn rttvar(&self) -> Duration {
+    self.rttvar
+  }
+
+  pub fn rto(&self) -> Duration {
+    let rto = match self.srtt {
+      Some(srtt) => srtt + GRANULARITY.max(self.rttvar * 4),
+      None => INITIAL_RTO,
+    };
+    rto.saturating_mul(1 << self.backoff).clamp(self.min_rto, self.max_rto)
+  }
+}
@@ -0,0 +1,136 @@
+use crate::constants::PacketPriority;
+use crate::protocol::datagram::InternalMessage;
+
+use std::cmp::{Ordering, Reverse};
+use std::collections::BinaryHeap;
+use num_traits::ToPrimitive;
+
+const NUMBER_OF_PRIORITIES: usize = 4;
+
+//RakNet's weight step, (1 << p) * p + p: Immediate 0, High 2, Medium 10, Low 27.
+//A lower priority is still sent, just proportionally less often.
+fn weight_step(priority: usize) -> u64 {
+  ((1u64 << priority) * priority as u64) + priority as u64
+}
+
+struct Entry {
+  weight: u64,
+  order: u64,
+  priority: usize,
+  message: InternalMessage,
+}
+
+impl PartialEq for Entry {
+  fn eq(&self, other: &Self) -> bool {
+    (self.weight, self.order) == (other.weight, other.order)
+  }
+}
+
+impl Eq for Entry {}
+
+impl PartialOrd for Entry {
+  fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+    Some(self.cmp(other))
+  }
+}
+
+impl Ord for Entry {
+  fn cmp(&self, other: &Self) -> Ordering {
+    (self.weight, self.order).cmp(&(other.weight, other.order))
+  }
+}
+
+pub struct Scheduler {
+  heap: BinaryHeap<Reverse<Entry>>,
+  next_weights: [u64; NUMBER_OF_PRIORITIES],
+  order: u64,
+}
+
+impl Default for Scheduler {
+  fn default() -> Self {
+    Scheduler {
+      heap: BinaryHeap::new(),
+      next_weights: Self::initial_weights(),
+      order: 0,
+    }
+  }
+}
+
+impl Scheduler {
+  pub fn new() -> Scheduler {
+    Default::default()
+  }
+
+  fn initial_weights() -> [u64; NUMBER_OF_PRIORITIES] {
+    std::array::from_fn(weight_step)
+  }
+
+  pub fn push(&mut self, message: InternalMessage, priority: PacketPriority) {
+    let priority = priority.to_usize().unwrap();
+    let step = weight_step(priority);
+    let weight = match self.heap.peek() {
+      None => {
+        self.next_weights = Self::initial_weights();
+        self.next_weights[priority]
+      },
+      //As in RakNet, a priority that was idle is placed one step after the head's predecessor, so it
+      //does not jump ahead of everything queued. Immediate has no step and goes before the head.
+      Some(Reverse(head)) => {
+        let min = head.weight.saturating_sub(weight_step(head.priority));
+        if priority == 0 || self.next_weights[priority] < min { min + step } else { self.next_weights[priority] }
+      },
+    };
+    self.next_weights[priority] = weight + step;
+    self.heap.push(Reverse(Entry { weight, order: self.order, priority, message }));
+    self.order += 1;
+  }
+
+  pub fn pop(&mut self) -> Option<InternalMessage> {
+    self.heap.pop().map(|Reverse(entry)| entry.message)
+  }
+
+  pub fn peek(&self) -> Option<&InternalMessage> {
+    self.heap.peek().map(|Reverse(entry)| &entry.message)
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.heap.is_empty()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn message(id: u8) -> InternalMessage {
+    InternalMessage {
+      payload: vec![id],
+      ..Default::default()
+    }
+  }
+
+  fn drain(scheduler: &mut Scheduler) -> String {
+    std::iter::from_fn(|| scheduler.pop()).map(|message| message.payload[0] as char).collect()
+  }
+
+  #[test]
+  fn immediate_preempts_queued_messages() {
+    let mut scheduler = Scheduler::new();
+    scheduler.push(message(b'm'), PacketPriority::Medium);
+    scheduler.push(message(b'l'), PacketPriority::Low);
+    scheduler.push(message(b'a'), PacketPriority::Immediate);
+    scheduler.push(message(b'b'), PacketPriority::Immediate);
+    assert_eq!(drain(&mut scheduler), "abml");
+  }
+
+  #[test]
+  fn priorities_interleave_by_weight() {
+    let mut scheduler = Scheduler::new();
+    for (id, priority) in [(b'L', PacketPriority::Low), (b'M', PacketPriority::Medium), (b'H', PacketPriority::High)] {
+      for _ in 0..6 {
+        scheduler.push(message(id), priority);
+      }
+    }
+    assert_eq!(drain(&mut scheduler), "HHHMHHHMLMMMLMLLLL");
+  }
+}
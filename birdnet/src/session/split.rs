@@ -0,0 +1,170 @@
+use crate::constants::PacketReliability;
+use crate::protocol::datagram::InternalMessage;
+use super::{Result, SessionError};
+
+use std::collections::HashMap;
+
+//flags(1) + length(2) + message index(3) + sequence(3) + order index(3) + order channel(1) + split(10)
+pub const MAX_MESSAGE_HEADER_SIZE: usize = 23;
+
+//Fragments are always sent reliably, since losing one loses the whole payload.
+fn reliable(reliability: PacketReliability) -> PacketReliability {
+  match reliability {
+    PacketReliability::Unreliable => PacketReliability::Reliable,
+    PacketReliability::UnreliableSequenced => PacketReliability::ReliableSequenced,
+    PacketReliability::UnreliableWithAckReceipt => PacketReliability::ReliableWithAckReceipt,
+    reliability => reliability,
+  }
+}
+
+#[derive(Default)]
+pub struct Splitter {
+  split_id: u16,
+}
+
+impl Splitter {
+  pub fn new() -> Splitter {
+    Default::default()
+  }
+
+  //Every fragment copies the reliability and ordering fields of `template`.
+  //Message indices are left for the caller to assign.
+  pub fn split(&mut self, template: &InternalMessage, payload: &[u8], fragment_size: usize) -> Vec<InternalMessage> {
+    let split_id = self.split_id;
+    self.split_id = self.split_id.wrapping_add(1);
+    let chunks = payload.chunks(fragment_size.max(1));
+    let split_count = chunks.len() as u32;
+    chunks.enumerate().map(|(split_index, chunk)| InternalMessage {
+      reliability: reliable(template.reliability),
+      splitted: true,
+      sequence: template.sequence,
+      order_index: template.order_index,
+      order_channel: template.order_channel,
+      split_count,
+      split_id,
+      split_index: split_index as u32,
+      payload: chunk.to_vec(),
+      ..Default::default()
+    }).collect()
+  }
+}
+
+struct Pending {
+  fragments: Vec<Option<Vec<u8>>>,
+  received: u32,
+}
+
+pub struct Assembler {
+  max_split_count: u32,
+  max_pending_splits: usize,
+  pending: HashMap<u16, Pending>,
+}
+
+impl Assembler {
+  pub fn new(max_split_count: u32, max_pending_splits: usize) -> Assembler {
+    Assembler {
+      max_split_count,
+      max_pending_splits,
+      pending: HashMap::new(),
+    }
+  }
+
+  //Returns the reassembled message once every fragment has arrived. A fragment that is inconsistent
+  //or exceeds the limits is an error, since it was already acknowledged and cannot just be dropped.
+  pub fn insert(&mut self, message: InternalMessage) -> Result<Option<InternalMessage>> {
+    if message.split_count == 0 || message.split_count > self.max_split_count || message.split_index >= message.split_count {
+      return Err(SessionError::SplitViolation);
+    }
+    if !self.pending.contains_key(&message.split_id) && self.pending.len() >= self.max_pending_splits {
+      return Err(SessionError::SplitViolation);
+    }
+    let pending = self.pending.entry(message.split_id).or_insert_with(|| Pending {
+      fragments: vec![None; message.split_count as usize],
+      received: 0,
+    });
+    if pending.fragments.len() != message.split_count as usize {
+      return Err(SessionError::SplitViolation);
+    }
+    //Only fragments of an unreliable split can be duplicated here.
+    let slot = &mut pending.fragments[message.split_index as usize];
+    if slot.is_some() {
+      return Ok(None);
+    }
+    *slot = Some(message.payload);
+    pending.received += 1;
+    if pending.received < message.split_count {
+      return Ok(None);
+    }
+
+    let pending = self.pending.remove(&message.split_id).unwrap();
+    Ok(Some(InternalMessage {
+      reliability: message.reliability,
+      message_index: message.message_index,
+      sequence: message.sequence,
+      order_index: message.order_index,
+      order_channel: message.order_channel,
+      payload: pending.fragments.into_iter().flatten().flatten().collect(),
+      ..Default::default()
+    }))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn template() -> InternalMessage {
+    InternalMessage {
+      reliability: PacketReliability::UnreliableSequenced,
+      sequence: 3,
+      order_index: 7,
+      order_channel: 2,
+      ..Default::default()
+    }
+  }
+
+  #[test]
+  fn split_fragments() {
+    let payload: Vec<u8> = (0..=255).collect();
+    let fragments = Splitter::new().split(&template(), &payload, 100);
+    assert_eq!(fragments.iter().map(|fragment| fragment.payload.len()).collect::<Vec<_>>(), [100, 100, 56]);
+    for (index, fragment) in fragments.iter().enumerate() {
+      assert!(fragment.splitted);
+      assert!(fragment.reliability == PacketReliability::ReliableSequenced);
+      assert_eq!((fragment.split_count, fragment.split_index), (3, index as u32));
+      assert_eq!((fragment.sequence, fragment.order_index, fragment.order_channel), (3, 7, 2));
+    }
+  }
+
+  #[test]
+  fn reassemble_out_of_order() {
+    let payload: Vec<u8> = (0..=255).collect();
+    let mut splitter = Splitter::new();
+    let first = splitter.split(&template(), &payload, 100);
+    let second = splitter.split(&template(), &payload[..150], 100);
+    let mut assembler = Assembler::new(8, 2);
+    for fragment in [&first[2], &second[1], &first[0]] {
+      assert!(assembler.insert(fragment.clone()).unwrap().is_none());
+    }
+    assert!(assembler.insert(first[0].clone()).unwrap().is_none());
+    let message = assembler.insert(first[1].clone()).unwrap().unwrap();
+    assert_eq!(message.payload, payload);
+    assert!(!message.splitted);
+    assert_eq!((message.sequence, message.order_index, message.order_channel), (3, 7, 2));
+    assert_eq!(assembler.insert(second[0].clone()).unwrap().unwrap().payload, &payload[..150]);
+  }
+
+  #[test]
+  fn limits_are_violations() {
+    let mut splitter = Splitter::new();
+    let mut assembler = Assembler::new(2, 1);
+    let too_many = splitter.split(&template(), &[0; 30], 10);
+    assert!(matches!(assembler.insert(too_many[0].clone()), Err(SessionError::SplitViolation)));
+    let pending = splitter.split(&template(), &[0; 20], 10);
+    assert!(assembler.insert(pending[0].clone()).unwrap().is_none());
+    let other = splitter.split(&template(), &[0; 20], 10);
+    assert!(matches!(assembler.insert(other[0].clone()), Err(SessionError::SplitViolation)));
+    let mismatched = InternalMessage { split_count: 1, split_index: 0, ..pending[1].clone() };
+    assert!(matches!(assembler.insert(mismatched), Err(SessionError::SplitViolation)));
+  }
+}
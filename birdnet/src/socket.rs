@@ -1,4 +1,247 @@
+use crate::constants::{MAXIMUM_MTU_SIZE, MINIMUM_MTU_SIZE, NUMBER_OF_ORDERED_STREAMS};
+use crate::protocol::version::SUPPORTED_PROTOCOL_VERSIONS;
+use crate::runtime::{self, UdpSocket};
+use crate::session::congestion::CongestionControlAlgorithm;
+
+use std::io;
+use std::net::SocketAddr;
+use std::time::Duration;
+use socket2::{Domain, Protocol, Socket, Type};
+
+//Everything the listener, connector and their sessions are tuned by. Only `Default` and
+//`SocketConfiguration::builder()` create one, so every configuration in use is valid.
 #[derive(Clone, Copy)]
 pub struct SocketConfiguration {
-  pub recv_buffer_size: usize,
+  pub(crate) recv_buffer_size: usize,
+  pub(crate) max_mtu_size: u16,
+  pub(crate) mtu_probe_attempts: usize,
+  pub(crate) mtu_probe_timeout: Duration,
+  pub(crate) congestion_control: CongestionControlAlgorithm,
+  pub(crate) min_rto: Duration,
+  pub(crate) max_rto: Duration,
+  pub(crate) ping_interval: Duration,
+  //A session that received nothing for this long is dropped.
+  pub(crate) timeout: Duration,
+  //How long a graceful close waits for queued messages and the peer's ACK.
+  pub(crate) close_timeout: Duration,
+  //How long the connector tries to complete a handshake.
+  pub(crate) connect_timeout: Duration,
+  //Retransmissions of one reliable message before the session is dropped as timed out.
+  pub(crate) max_resends: u32,
+  //Fragments of one split message, and split messages reassembled at once per session.
+  //A peer exceeding either is disconnected.
+  pub(crate) max_split_count: u32,
+  pub(crate) max_pending_splits: usize,
+  //Ordering channels a session keeps, at most 256 since the channel is a byte on the wire.
+  pub(crate) ordering_channels: usize,
+  //Sessions the listener keeps open at once, handshakes included.
+  pub(crate) max_connections: usize,
+  //Minimum time between two handshakes from the same IP, zero to disable.
+  pub(crate) reconnect_interval: Duration,
+  //Sent as server id in offline replies and as client id in handshakes. None picks a random one.
+  pub(crate) guid: Option<u64>,
+  //RakNet protocol versions, most preferred first. The listener accepts these and
+  //the connector tries them in order.
+  pub(crate) protocol_versions: &'static [u8],
+  //Linux only: datagrams read or written per recvmmsg/sendmmsg call. 1 uses recv_from and send_to,
+  //which is also what other platforms do.
+  pub(crate) batch_size: usize,
+  //Linux only, with batching: lets the kernel split and coalesce datagrams with UDP GSO/GRO,
+  //if it supports them.
+  pub(crate) segmentation_offload: bool,
+  //Options of sockets created by `bind`, None keeps the system default.
+  pub(crate) socket_recv_buffer_size: Option<usize>,
+  pub(crate) socket_send_buffer_size: Option<usize>,
+  pub(crate) ipv6_only: Option<bool>,
+  //Unix only: lets several sockets bind the same port, e.g. one listener per thread.
+  pub(crate) reuse_port: bool,
+}
+
+impl Default for SocketConfiguration {
+  fn default() -> Self {
+    SocketConfiguration {
+      recv_buffer_size: 4096,
+      max_mtu_size: MAXIMUM_MTU_SIZE,
+      mtu_probe_attempts: 2,
+      mtu_probe_timeout: Duration::from_millis(500),
+      congestion_control: Default::default(),
+      min_rto: Duration::from_millis(100),
+      max_rto: Duration::from_secs(5),
+      ping_interval: Duration::from_secs(5),
+      timeout: Duration::from_secs(10),
+      close_timeout: Duration::from_secs(3),
+      connect_timeout: Duration::from_secs(10),
+      max_resends: 16,
+      max_split_count: 256,
+      max_pending_splits: 32,
+      ordering_channels: NUMBER_OF_ORDERED_STREAMS,
+      max_connections: 1024,
+      reconnect_interval: Duration::ZERO,
+      guid: None,
+      protocol_versions: SUPPORTED_PROTOCOL_VERSIONS,
+      batch_size: 1,
+      segmentation_offload: true,
+      socket_recv_buffer_size: None,
+      socket_send_buffer_size: None,
+      ipv6_only: None,
+      reuse_port: false,
+    }
+  }
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum InvalidConfiguration {
+  //Outside MINIMUM_MTU_SIZE..=MAXIMUM_MTU_SIZE.
+  MaxMtuSize(u16),
+  //Smaller than the largest datagram.
+  RecvBufferSize(usize),
+  //A zero duration, a timeout not longer than the ping interval, or min_rto above max_rto.
+  Timing,
+  MtuProbeAttempts,
+  MaxResends,
+  SplitLimits,
+  OrderingChannels(usize),
+  MaxConnections,
+  ProtocolVersions,
+  BatchSize,
+}
+
+impl SocketConfiguration {
+  pub fn builder() -> SocketConfigurationBuilder {
+    SocketConfigurationBuilder {
+      config: Default::default(),
+    }
+  }
+
+  fn validate(&self) -> Result<(), InvalidConfiguration> {
+    if !(MINIMUM_MTU_SIZE..=MAXIMUM_MTU_SIZE).contains(&self.max_mtu_size) {
+      return Err(InvalidConfiguration::MaxMtuSize(self.max_mtu_size));
+    }
+    if self.recv_buffer_size < self.max_mtu_size as usize {
+      return Err(InvalidConfiguration::RecvBufferSize(self.recv_buffer_size));
+    }
+    let durations = [self.mtu_probe_timeout, self.min_rto, self.ping_interval, self.timeout, self.close_timeout, self.connect_timeout];
+    if durations.contains(&Duration::ZERO) || self.timeout <= self.ping_interval || self.min_rto > self.max_rto {
+      return Err(InvalidConfiguration::Timing);
+    }
+    if self.mtu_probe_attempts == 0 {
+      return Err(InvalidConfiguration::MtuProbeAttempts);
+    }
+    if self.max_resends == 0 {
+      return Err(InvalidConfiguration::MaxResends);
+    }
+    if self.max_split_count == 0 || self.max_pending_splits == 0 {
+      return Err(InvalidConfiguration::SplitLimits);
+    }
+    if !(1..=u8::MAX as usize + 1).contains(&self.ordering_channels) {
+      return Err(InvalidConfiguration::OrderingChannels(self.ordering_channels));
+    }
+    if self.max_connections == 0 {
+      return Err(InvalidConfiguration::MaxConnections);
+    }
+    if self.protocol_versions.is_empty() {
+      return Err(InvalidConfiguration::ProtocolVersions);
+    }
+    if self.batch_size == 0 {
+      return Err(InvalidConfiguration::BatchSize);
+    }
+    Ok(())
+  }
+
+  //Binds a socket with the configured socket options, for `Listener::with_configuration`.
+  //With tokio, this has to be called inside the runtime.
+  pub fn bind(&self, address: SocketAddr) -> io::Result<UdpSocket> {
+    let socket = Socket::new(Domain::for_address(address), Type::DGRAM, Some(Protocol::UDP))?;
+    if let Some(size) = self.socket_recv_buffer_size {
+      socket.set_recv_buffer_size(size)?;
+    }
+    if let Some(size) = self.socket_send_buffer_size {
+      socket.set_send_buffer_size(size)?;
+    }
+    if let (Some(only_v6), true) = (self.ipv6_only, address.is_ipv6()) {
+      socket.set_only_v6(only_v6)?;
+    }
+    if self.reuse_port {
+      reuse_port(&socket)?;
+    }
+    socket.set_nonblocking(true)?;
+    socket.bind(&address.into())?;
+    runtime::from_std(socket.into())
+  }
+}
+
+#[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin"))))]
+fn reuse_port(socket: &Socket) -> io::Result<()> {
+  socket.set_reuse_port(true)
+}
+
+#[cfg(not(all(unix, not(any(target_os = "solaris", target_os = "illumos", target_os = "cygwin")))))]
+fn reuse_port(_: &Socket) -> io::Result<()> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "SO_REUSEPORT is not available on this platform"))
+}
+
+pub struct SocketConfigurationBuilder {
+  config: SocketConfiguration,
+}
+
+macro_rules! setters {
+  ($($field:ident: $type:ty),* $(,)?) => {
+    $(
+      pub fn $field(mut self, $field: $type) -> Self {
+        self.config.$field = $field;
+        self
+      }
+    )*
+  };
+}
+
+impl SocketConfigurationBuilder {
+  setters! {
+    recv_buffer_size: usize,
+    max_mtu_size: u16,
+    mtu_probe_attempts: usize,
+    mtu_probe_timeout: Duration,
+    congestion_control: CongestionControlAlgorithm,
+    min_rto: Duration,
+    max_rto: Duration,
+    ping_interval: Duration,
+    timeout: Duration,
+    close_timeout: Duration,
+    connect_timeout: Duration,
+    max_resends: u32,
+    max_split_count: u32,
+    max_pending_splits: usize,
+    ordering_channels: usize,
+    max_connections: usize,
+    reconnect_interval: Duration,
+    protocol_versions: &'static [u8],
+    batch_size: usize,
+    segmentation_offload: bool,
+    reuse_port: bool,
+  }
+
+  pub fn guid(mut self, guid: u64) -> Self {
+    self.config.guid = Some(guid);
+    self
+  }
+
+  pub fn socket_recv_buffer_size(mut self, size: usize) -> Self {
+    self.config.socket_recv_buffer_size = Some(size);
+    self
+  }
+
+  pub fn socket_send_buffer_size(mut self, size: usize) -> Self {
+    self.config.socket_send_buffer_size = Some(size);
+    self
+  }
+
+  pub fn ipv6_only(mut self, only_v6: bool) -> Self {
+    self.config.ipv6_only = Some(only_v6);
+    self
+  }
+
+  pub fn build(self) -> Result<SocketConfiguration, InvalidConfiguration> {
+    self.config.validate()?;
+    Ok(self.config)
+  }
 }
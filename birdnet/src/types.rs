@@ -1,5 +1,5 @@
 use crate::codable::{self, Codable, BytesCodingError, ReadBytesExt, WriteBytesExt};
-use async_std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
 use bytes::{Buf, BufMut};
 
 pub struct RakString(pub String);
@@ -45,7 +45,7 @@ impl Codable for SystemAddress {
     match self.0 {
       SocketAddr::V4(sockv4) => {
         buffer.write_u8(4)?;
-        buffer.write_u32_be(!u32::from(sockv4.ip().clone()))?;
+        buffer.write_u32_be(!u32::from(*sockv4.ip()))?;
         buffer.write_u16_be(sockv4.port())?;
         Ok(())
       },
@@ -54,7 +54,7 @@ impl Codable for SystemAddress {
         buffer.write_u16_le(10)?;
         buffer.write_u16_be(sockv6.port())?;
         buffer.write_u32_le(sockv6.flowinfo())?;
-        buffer.write_u128_be(u128::from(sockv6.ip().clone()))?;
+        buffer.write_u128_be(u128::from(*sockv6.ip()))?;
         buffer.write_u32_le(sockv6.scope_id())?;
         Ok(())
       },
@@ -64,12 +64,12 @@ impl Codable for SystemAddress {
   fn decode(mut buffer: &mut dyn Buf) -> codable::Result<Self> {
     match buffer.read_u8()? {
       4 => {
-        let addr = Ipv4Addr::from(buffer.read_u32_be()?);
+        let addr = Ipv4Addr::from(!buffer.read_u32_be()?);
         let port = buffer.read_u16_be()?;
         Ok(SystemAddress(SocketAddr::V4(SocketAddrV4::new(addr, port))))
       },
       6 => {
-        buffer.read_u8()?;//family == AF_INET6(10)
+        buffer.read_u16_le()?;//family == AF_INET6(10)
         let port = buffer.read_u16_be()?;
         let flowinfo = buffer.read_u32_le()?;
         let addr = Ipv6Addr::from(buffer.read_u128_be()?);
@@ -0,0 +1,330 @@
+use super::{is_fatal, is_oversized};
+use crate::endpoint::Transmit;
+use crate::runtime::{RawSocket, UdpSocket};
+use crate::socket::SocketConfiguration;
+
+use std::io;
+use std::mem;
+use std::net::{SocketAddr, SocketAddrV4, SocketAddrV6, Ipv4Addr, Ipv6Addr};
+use std::ops::Range;
+use std::os::fd::RawFd;
+use std::ptr;
+use std::sync::Arc;
+use libc::{c_int, c_void, socklen_t};
+use log::{debug, warn};
+
+//Largest datagram GRO hands up and GSO accepts.
+const MAX_SEGMENTED_SIZE: usize = u16::MAX as usize;
+//The kernel's UDP_MAX_SEGMENTS.
+const MAX_SEGMENTS: usize = 64;
+
+//Room for one cmsg carrying an int, aligned like cmsghdr.
+#[derive(Clone, Copy)]
+#[repr(C, align(8))]
+struct Control([u8; 32]);
+
+//Consecutive transmits to one destination, sent as a single GSO datagram when there are several.
+struct Group {
+  destination: SocketAddr,
+  transmits: Range<usize>,
+  segment_size: usize,
+}
+
+//recvmmsg/sendmmsg on the socket, with UDP GRO/GSO if enabled and supported.
+pub(super) struct Batch {
+  socket: RawSocket,
+  size: usize,
+  slot_size: usize,
+  gso: bool,
+  gro: bool,
+}
+
+impl Batch {
+  pub fn new(socket: &Arc<UdpSocket>, config: &SocketConfiguration) -> io::Result<Batch> {
+    let socket = RawSocket::new(socket)?;
+    let fd = socket.as_raw_fd();
+    //A default segment size of 0 leaves sends unsegmented, but the option fails without kernel support.
+    let gso = config.segmentation_offload && set_option(fd, libc::UDP_SEGMENT, 0).is_ok();
+    let gro = config.segmentation_offload && set_option(fd, libc::UDP_GRO, 1).is_ok();
+    Ok(Batch {
+      socket,
+      size: config.batch_size,
+      slot_size: if gro { MAX_SEGMENTED_SIZE } else { config.recv_buffer_size },
+      gso,
+      gro,
+    })
+  }
+
+  //The receive buffer holds one slot per message of a batch.
+  pub fn buffer_size(&self) -> usize {
+    self.size * self.slot_size
+  }
+
+  pub async fn recv(&mut self, buffer: &mut [u8], received: &mut Vec<(SocketAddr, Range<usize>)>) -> io::Result<()> {
+    let slot_size = self.slot_size;
+    let mut names = vec![unsafe { mem::zeroed::<libc::sockaddr_storage>() }; self.size];
+    let mut controls = vec![Control([0; 32]); self.size];
+    //Length, name length, GRO segment size and truncation of each message.
+    let mut messages = Vec::with_capacity(self.size);
+    self.socket.read_with(|fd| {
+      let mut iovecs: Vec<libc::iovec> = buffer.chunks_mut(slot_size).map(|slot| libc::iovec {
+        iov_base: slot.as_mut_ptr().cast(),
+        iov_len: slot.len(),
+      }).collect();
+      let mut headers: Vec<libc::mmsghdr> = iovecs.iter_mut().zip(names.iter_mut()).zip(controls.iter_mut())
+        .map(|((iovec, name), control)| {
+          let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+          header.msg_hdr.msg_name = (name as *mut libc::sockaddr_storage).cast();
+          header.msg_hdr.msg_namelen = mem::size_of::<libc::sockaddr_storage>() as socklen_t;
+          header.msg_hdr.msg_iov = iovec;
+          header.msg_hdr.msg_iovlen = 1;
+          header.msg_hdr.msg_control = control.0.as_mut_ptr().cast();
+          header.msg_hdr.msg_controllen = mem::size_of::<Control>() as _;
+          header
+        }).collect();
+      let count = unsafe { libc::recvmmsg(fd, headers.as_mut_ptr(), headers.len() as u32, 0, ptr::null_mut()) };
+      if count < 0 {
+        return Err(io::Error::last_os_error());
+      }
+      messages.clear();
+      for header in &headers[..count as usize] {
+        let truncated = header.msg_hdr.msg_flags & libc::MSG_TRUNC != 0;
+        messages.push((header.msg_len as usize, header.msg_hdr.msg_namelen, unsafe { gro_segment_size(&header.msg_hdr) }, truncated));
+      }
+      Ok(())
+    }).await?;
+    for (index, (length, name_length, segment_size, truncated)) in messages.into_iter().enumerate() {
+      let address = match from_sockaddr(&names[index], name_length) {
+        Some(address) if !truncated => address,
+        _ => continue,
+      };
+      let start = index * slot_size;
+      let segment_size = segment_size.filter(|size| *size > 0 && self.gro).unwrap_or(length).max(1);
+      for offset in (0..length).step_by(segment_size) {
+        received.push((address, start + offset..start + length.min(offset + segment_size)));
+      }
+    }
+    Ok(())
+  }
+
+  pub async fn send(&mut self, transmits: &[Transmit], oversized: &mut Vec<(SocketAddr, usize)>) -> io::Result<()> {
+    let mut next = 0;
+    while next < transmits.len() {
+      let pending = &transmits[next..];
+      let groups = self.group(pending);
+      let names: Vec<(libc::sockaddr_storage, socklen_t)> = groups.iter().map(|group| to_sockaddr(group.destination)).collect();
+      let result = self.socket.write_with(|fd| {
+        let mut iovecs: Vec<libc::iovec> = pending.iter().map(|transmit| libc::iovec {
+          iov_base: transmit.payload.as_ptr() as *mut c_void,
+          iov_len: transmit.payload.len(),
+        }).collect();
+        let mut controls = vec![Control([0; 32]); groups.len()];
+        let mut headers: Vec<libc::mmsghdr> = groups.iter().zip(&names).zip(controls.iter_mut())
+          .map(|((group, (name, name_length)), control)| {
+            let mut header: libc::mmsghdr = unsafe { mem::zeroed() };
+            header.msg_hdr.msg_name = name as *const libc::sockaddr_storage as *mut c_void;
+            header.msg_hdr.msg_namelen = *name_length;
+            header.msg_hdr.msg_iov = unsafe { iovecs.as_mut_ptr().add(group.transmits.start) };
+            header.msg_hdr.msg_iovlen = group.transmits.len() as _;
+            if group.transmits.len() > 1 {
+              unsafe { set_gso_segment_size(&mut header.msg_hdr, control, group.segment_size as u16) };
+            }
+            header
+          }).collect();
+        let count = unsafe { libc::sendmmsg(fd, headers.as_mut_ptr(), headers.len() as u32, 0) };
+        if count < 0 {
+          return Err(io::Error::last_os_error());
+        }
+        Ok(count as usize)
+      }).await;
+      match result {
+        Ok(0) => return Err(io::ErrorKind::WriteZero.into()),
+        Ok(count) => next += groups[..count].iter().map(|group| group.transmits.len()).sum::<usize>(),
+        //Interfaces without checksum offload refuse GSO sends.
+        Err(e) if e.raw_os_error() == Some(libc::EIO) && self.gso && groups[0].transmits.len() > 1 => {
+          warn!("UDP GSO was refused, sending unsegmented: {}", e);
+          self.gso = false;
+        },
+        Err(e) if is_fatal(&e) => return Err(e),
+        Err(e) if is_oversized(&e) => {
+          oversized.extend(pending[groups[0].transmits.clone()].iter().map(|transmit| (transmit.destination, transmit.payload.len())));
+          next += groups[0].transmits.len();
+        },
+        //sendmmsg fails on the first group only, e.g. EINVAL or EPERM for its destination.
+        Err(e) => {
+          debug!("dropping datagrams to {}: {}", groups[0].destination, e);
+          next += groups[0].transmits.len();
+        },
+      }
+    }
+    Ok(())
+  }
+
+  //Splits the front of `transmits` into at most one batch of groups. With GSO, a group holds
+  //transmits of one size, except a shorter last one.
+  fn group(&self, transmits: &[Transmit]) -> Vec<Group> {
+    let mut groups: Vec<Group> = Vec::new();
+    let mut length = 0;
+    for (index, transmit) in transmits.iter().enumerate() {
+      let size = transmit.payload.len();
+      if let Some(group) = groups.last_mut() {
+        let joins = self.gso
+          && group.destination == transmit.destination
+          && group.transmits.len() < MAX_SEGMENTS
+          && size <= group.segment_size
+          && length == group.segment_size * group.transmits.len()
+          && length + size <= MAX_SEGMENTED_SIZE;
+        if joins {
+          group.transmits.end += 1;
+          length += size;
+          continue;
+        }
+      }
+      if groups.len() == self.size {
+        break;
+      }
+      groups.push(Group {
+        destination: transmit.destination,
+        transmits: index..index + 1,
+        segment_size: size,
+      });
+      length = size;
+    }
+    groups
+  }
+}
+
+fn set_option(fd: RawFd, name: c_int, value: c_int) -> io::Result<()> {
+  let result = unsafe {
+    libc::setsockopt(fd, libc::SOL_UDP, name, (&value as *const c_int).cast(), mem::size_of::<c_int>() as socklen_t)
+  };
+  if result < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+unsafe fn gro_segment_size(header: &libc::msghdr) -> Option<usize> {
+  let mut cmsg = libc::CMSG_FIRSTHDR(header);
+  while !cmsg.is_null() {
+    if (*cmsg).cmsg_level == libc::SOL_UDP && (*cmsg).cmsg_type == libc::UDP_GRO {
+      return Some(ptr::read_unaligned(libc::CMSG_DATA(cmsg) as *const c_int) as usize);
+    }
+    cmsg = libc::CMSG_NXTHDR(header, cmsg);
+  }
+  None
+}
+
+unsafe fn set_gso_segment_size(header: &mut libc::msghdr, control: &mut Control, segment_size: u16) {
+  header.msg_control = control.0.as_mut_ptr().cast();
+  header.msg_controllen = libc::CMSG_SPACE(mem::size_of::<u16>() as u32) as _;
+  let cmsg = libc::CMSG_FIRSTHDR(header);
+  (*cmsg).cmsg_level = libc::SOL_UDP;
+  (*cmsg).cmsg_type = libc::UDP_SEGMENT;
+  (*cmsg).cmsg_len = libc::CMSG_LEN(mem::size_of::<u16>() as u32) as _;
+  ptr::write_unaligned(libc::CMSG_DATA(cmsg) as *mut u16, segment_size);
+}
+
+fn to_sockaddr(address: SocketAddr) -> (libc::sockaddr_storage, socklen_t) {
+  let mut storage: libc::sockaddr_storage = unsafe { mem::zeroed() };
+  let length = match address {
+    SocketAddr::V4(address) => {
+      let sockaddr = libc::sockaddr_in {
+        sin_family: libc::AF_INET as libc::sa_family_t,
+        sin_port: address.port().to_be(),
+        sin_addr: libc::in_addr { s_addr: u32::from_ne_bytes(address.ip().octets()) },
+        sin_zero: [0; 8],
+      };
+      unsafe { ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), sockaddr) };
+      mem::size_of::<libc::sockaddr_in>()
+    },
+    SocketAddr::V6(address) => {
+      let mut sockaddr: libc::sockaddr_in6 = unsafe { mem::zeroed() };
+      sockaddr.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+      sockaddr.sin6_port = address.port().to_be();
+      sockaddr.sin6_flowinfo = address.flowinfo();
+      sockaddr.sin6_addr = libc::in6_addr { s6_addr: address.ip().octets() };
+      sockaddr.sin6_scope_id = address.scope_id();
+      unsafe { ptr::write((&mut storage as *mut libc::sockaddr_storage).cast(), sockaddr) };
+      mem::size_of::<libc::sockaddr_in6>()
+    },
+  };
+  (storage, length as socklen_t)
+}
+
+fn from_sockaddr(storage: &libc::sockaddr_storage, length: socklen_t) -> Option<SocketAddr> {
+  let length = length as usize;
+  match storage.ss_family as c_int {
+    libc::AF_INET if length >= mem::size_of::<libc::sockaddr_in>() => {
+      let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in>() };
+      let ip = Ipv4Addr::from(sockaddr.sin_addr.s_addr.to_ne_bytes());
+      Some(SocketAddrV4::new(ip, u16::from_be(sockaddr.sin_port)).into())
+    },
+    libc::AF_INET6 if length >= mem::size_of::<libc::sockaddr_in6>() => {
+      let sockaddr = unsafe { &*(storage as *const libc::sockaddr_storage).cast::<libc::sockaddr_in6>() };
+      let ip = Ipv6Addr::from(sockaddr.sin6_addr.s6_addr);
+      Some(SocketAddrV6::new(ip, u16::from_be(sockaddr.sin6_port), sockaddr.sin6_flowinfo, sockaddr.sin6_scope_id).into())
+    },
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::super::UdpIo;
+  use super::*;
+  use crate::runtime;
+  use std::time::Duration;
+
+  fn transmit(destination: SocketAddr, id: u8, size: usize) -> Transmit {
+    Transmit { destination, payload: vec![id; size] }
+  }
+
+  async fn receive(io: &mut UdpIo, count: usize) -> Vec<(SocketAddr, Vec<u8>)> {
+    let mut received = Vec::new();
+    while received.len() < count {
+      runtime::timeout(Duration::from_secs(2), io.recv()).await.expect("timed out").unwrap();
+      received.extend(io.received().map(|(address, payload)| (address, payload.to_vec())));
+    }
+    received
+  }
+
+  //Runs of datagrams to one destination are grouped, and with offload sent and received as segments.
+  async fn exchange(address: &str, segmentation_offload: bool) {
+    let config = SocketConfiguration::builder().batch_size(8).segmentation_offload(segmentation_offload).build().unwrap();
+    let bind = || config.bind(address.parse().unwrap()).map(Arc::new);
+    let (sender, first, second) = match (bind(), bind(), bind()) {
+      (Ok(sender), Ok(first), Ok(second)) => (sender, first, second),
+      //No IPv6 loopback on this host.
+      _ => return,
+    };
+    let from = sender.local_addr().unwrap();
+    let (to_first, to_second) = (first.local_addr().unwrap(), second.local_addr().unwrap());
+    let mut sender = UdpIo::new(sender, &config);
+    let (mut first, mut second) = (UdpIo::new(first, &config), UdpIo::new(second, &config));
+    let mtu_size = config.max_mtu_size as usize - 28;
+    let mut transmits: Vec<_> = (0..5).map(|id| transmit(to_first, id, mtu_size)).collect();
+    transmits.extend((5..8).map(|id| transmit(to_second, id, mtu_size)));
+    transmits.extend((8..11).map(|id| transmit(to_first, id, mtu_size)));
+    transmits.push(transmit(to_first, 11, mtu_size / 2));
+    sender.send(&transmits).await.unwrap();
+    assert_eq!(sender.oversized().count(), 0);
+    let mut received = receive(&mut first, 9).await;
+    received.extend(receive(&mut second, 3).await);
+    let expected: Vec<_> = [to_first, to_second].into_iter()
+      .flat_map(|to| transmits.iter().filter(move |transmit| transmit.destination == to))
+      .map(|transmit| (from, transmit.payload.clone()))
+      .collect();
+    assert_eq!(received, expected);
+  }
+
+  #[test]
+  fn batched_loopback_exchange() {
+    runtime::block_on(async {
+      for segmentation_offload in [false, true] {
+        exchange("127.0.0.1:0", segmentation_offload).await;
+        exchange("[::1]:0", segmentation_offload).await;
+      }
+    });
+  }
+}
@@ -0,0 +1,162 @@
+use crate::endpoint::Transmit;
+use crate::runtime::UdpSocket;
+use crate::socket::SocketConfiguration;
+
+use std::io;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::sync::Arc;
+use log::debug;
+
+#[cfg(target_os = "linux")]
+mod linux;
+
+//Reads and writes a driver's datagrams, many per syscall where the platform and configuration allow it.
+pub(crate) struct UdpIo {
+  socket: Arc<UdpSocket>,
+  buffer: Vec<u8>,
+  //The datagrams of the last `recv`, as ranges of `buffer`.
+  received: Vec<(SocketAddr, Range<usize>)>,
+  //A connector's socket is connected to the server, and BSDs refuse send_to on it with EISCONN.
+  connected: bool,
+  //Destinations and sizes of datagrams the last `send` dropped as larger than the path MTU.
+  oversized: Vec<(SocketAddr, usize)>,
+  #[cfg(target_os = "linux")]
+  batch: Option<linux::Batch>,
+}
+
+impl UdpIo {
+  pub fn new(socket: Arc<UdpSocket>, config: &SocketConfiguration) -> UdpIo {
+    #[cfg(target_os = "linux")]
+    let batch = match config.batch_size {
+      0 | 1 => None,
+      _ => match linux::Batch::new(&socket, config) {
+        Ok(batch) => Some(batch),
+        Err(e) => {
+          log::warn!("batched socket I/O unavailable, falling back to recv_from: {}", e);
+          None
+        },
+      },
+    };
+    #[cfg(target_os = "linux")]
+    let buffer_size = batch.as_ref().map_or(config.recv_buffer_size, |batch| batch.buffer_size());
+    #[cfg(not(target_os = "linux"))]
+    let buffer_size = config.recv_buffer_size;
+    UdpIo {
+      connected: socket.peer_addr().is_ok(),
+      socket,
+      buffer: vec![0u8; buffer_size],
+      received: Vec::new(),
+      oversized: Vec::new(),
+      #[cfg(target_os = "linux")]
+      batch,
+    }
+  }
+
+  //Waits for at least one datagram, then yields them through `received`.
+  pub async fn recv(&mut self) -> io::Result<()> {
+    self.received.clear();
+    #[cfg(target_os = "linux")]
+    if let Some(batch) = &mut self.batch {
+      return batch.recv(&mut self.buffer, &mut self.received).await;
+    }
+    let (size, address) = self.socket.recv_from(&mut self.buffer).await?;
+    self.received.push((address, 0..size));
+    Ok(())
+  }
+
+  pub fn received(&self) -> impl Iterator<Item = (SocketAddr, &[u8])> {
+    self.received.iter().map(|(address, range)| (*address, &self.buffer[range.clone()]))
+  }
+
+  //Datagrams that fail to send are dropped, which reliable messages recover from. Only errors
+  //leaving the socket unusable are returned.
+  pub async fn send(&mut self, transmits: &[Transmit]) -> io::Result<()> {
+    self.oversized.clear();
+    #[cfg(target_os = "linux")]
+    if let Some(batch) = &mut self.batch {
+      return batch.send(transmits, &mut self.oversized).await;
+    }
+    for transmit in transmits {
+      let result = if self.connected {
+        self.socket.send(&transmit.payload).await
+      }
+      else {
+        self.socket.send_to(&transmit.payload, transmit.destination).await
+      };
+      match result {
+        Ok(_) => {},
+        Err(e) if is_fatal(&e) => return Err(e),
+        Err(e) if is_oversized(&e) => self.oversized.push((transmit.destination, transmit.payload.len())),
+        Err(e) => debug!("dropping datagram to {}: {}", transmit.destination, e),
+      }
+    }
+    Ok(())
+  }
+
+  pub fn oversized(&self) -> impl Iterator<Item = (SocketAddr, usize)> + '_ {
+    self.oversized.iter().copied()
+  }
+}
+
+//Errors of the socket itself rather than of one datagram, e.g. EINVAL for port 0 or EPERM from a
+//firewall rule only fail that datagram.
+#[cfg(unix)]
+pub(crate) fn is_fatal(e: &io::Error) -> bool {
+  matches!(e.raw_os_error(), Some(libc::EBADF | libc::ENOTSOCK | libc::EFAULT | libc::ENOTCONN))
+}
+
+#[cfg(windows)]
+pub(crate) fn is_fatal(e: &io::Error) -> bool {
+  //WSAEBADF, WSAEFAULT, WSAENOTSOCK, WSAENOTCONN, WSANOTINITIALISED
+  matches!(e.raw_os_error(), Some(10009 | 10014 | 10038 | 10057 | 10093))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn is_fatal(_: &io::Error) -> bool {
+  false
+}
+
+//EMSGSIZE, a datagram larger than the path MTU with DF set.
+#[cfg(unix)]
+pub(crate) fn is_oversized(e: &io::Error) -> bool {
+  e.raw_os_error() == Some(libc::EMSGSIZE)
+}
+
+#[cfg(windows)]
+pub(crate) fn is_oversized(e: &io::Error) -> bool {
+  //WSAEMSGSIZE
+  e.raw_os_error() == Some(10040)
+}
+
+#[cfg(not(any(unix, windows)))]
+pub(crate) fn is_oversized(_: &io::Error) -> bool {
+  false
+}
+
+//Sets DF on the socket's datagrams, so that MTU probes larger than the path fail instead of
+//being fragmented, or clears it to let the system fragment them again.
+#[cfg(any(target_os = "linux", target_os = "android", target_vendor = "apple", target_os = "freebsd"))]
+pub(crate) fn set_dont_fragment(socket: &UdpSocket, enabled: bool) -> io::Result<()> {
+  use std::os::fd::AsRawFd;
+  #[cfg(any(target_os = "linux", target_os = "android"))]
+  let ipv4 = (libc::IP_MTU_DISCOVER, if enabled { libc::IP_PMTUDISC_DO } else { libc::IP_PMTUDISC_DONT });
+  #[cfg(not(any(target_os = "linux", target_os = "android")))]
+  let ipv4 = (libc::IP_DONTFRAG, enabled as libc::c_int);
+  let (level, (name, value)) = match socket.local_addr()? {
+    SocketAddr::V4(_) => (libc::IPPROTO_IP, ipv4),
+    SocketAddr::V6(_) => (libc::IPPROTO_IPV6, (libc::IPV6_DONTFRAG, enabled as libc::c_int)),
+  };
+  let result = unsafe {
+    libc::setsockopt(socket.as_raw_fd(), level, name, (&value as *const libc::c_int).cast(), std::mem::size_of::<libc::c_int>() as libc::socklen_t)
+  };
+  if result < 0 {
+    return Err(io::Error::last_os_error());
+  }
+  Ok(())
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_vendor = "apple", target_os = "freebsd")))]
+pub(crate) fn set_dont_fragment(_: &UdpSocket, _: bool) -> io::Result<()> {
+  Err(io::Error::new(io::ErrorKind::Unsupported, "DF cannot be set on this platform"))
+}